@@ -1,99 +1,335 @@
-#[cfg(not(feature = "sqlite"))]
-use crate::state::server::{InMemoryBlockStore, InMemoryTransactionPool};
-#[cfg(feature = "sqlite")]
-use crate::state::server::{SqLiteBlockStore, SqLiteTransactionPool};
 use crate::{
-    consensus::logic::{current_round, evaluate_commitment, get_committing_validator},
-    crypto::ecdsa::deserialize_vk,
-    handlers::handle_block_proposal,
-    types::{Block, ConsensusCommitment, Transaction},
+    consensus::logic::{
+        current_round, evaluate_commitment, verify_commitment, verify_equivocation_evidence,
+        verify_governance_transaction, verify_timeout_certificate, verify_timeout_vote,
+        verify_transaction_envelope,
+    },
+    exclusion::SequencedTree,
+    get_current_time,
+    gossipper::GENESIS_HASH_HEADER,
+    handlers::{evict_expired_transactions, handle_block_proposal},
+    state::{server::PoolEntry, trie::StagedTrie},
+    synchronization::SYNC_RANGE_SIZE,
+    types::{
+        transaction_key, Block, CertifiedBlock, ConsensusCommitment, EquivocationEvidence,
+        GenericHash, HeightExclusionProof, HeightProof, NodeStatus, TimeoutCertificate,
+        TimeoutVote, Transaction, TransactionStatus,
+    },
     ServerState,
 };
-use axum::{extract::Path, Extension, Json};
+use axum::{
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, StatusCode},
+    Extension, Json,
+};
 use colored::Colorize;
 use k256::ecdsa::{signature::Verifier, Signature};
-use l2_sequencer::config::consensus::ROUND_DURATION;
 use patricia_trie::store::types::Node;
-use std::sync::Arc;
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::RwLock;
+// storage errors are reported to the caller instead of panicking the node
+fn storage_error(e: anyhow::Error) -> String {
+    println!("{}", format_args!("{} Storage: {:#}", "[Error]".red(), e));
+    format!("[Err] Storage error: {}", e)
+}
+// requests between peers must carry our genesis hash
+fn is_same_network(headers: &HeaderMap, genesis_hash: &str) -> bool {
+    match headers.get(GENESIS_HASH_HEADER) {
+        Some(peer_genesis_hash) => peer_genesis_hash.to_str().ok() == Some(genesis_hash),
+        None => false,
+    }
+}
 pub async fn schedule(
     Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    ConnectInfo(client_address): ConnectInfo<SocketAddr>,
     Json(transaction): Json<Transaction>,
 ) -> String {
     let mut state = shared_state.write().await;
-    let success_response =
-        format!("[Ok] Transaction is being sequenced: {:?}", &transaction).to_string();
-    state.pool_state.insert_transaction(transaction);
+    // expired transactions don't count towards the limits
+    if let Err(e) = evict_expired_transactions(&mut state) {
+        return storage_error(e);
+    }
+    let transaction_hash = transaction.hash();
+    match state.block_state.contains_transaction(&transaction_hash) {
+        Ok(true) => return "[Err] Transaction was already finalized".to_string(),
+        Ok(false) => {}
+        Err(e) => return storage_error(e),
+    }
+    match state.pool_state.contains_transaction(&transaction_hash) {
+        Ok(true) => return "[Err] Transaction is already pending".to_string(),
+        Ok(false) => {}
+        Err(e) => return storage_error(e),
+    }
+    if let Err(e) = verify_transaction_envelope(
+        &transaction,
+        &state.genesis.chain_id,
+        state.genesis.require_signed_transactions,
+    ) {
+        return format!("[Err] Transaction was rejected: {}", e);
+    }
+    if let Some(envelope) = &transaction.envelope {
+        // nonces below the sender's next nonce were used by finalized transactions
+        match state.block_state.get_next_nonce(&envelope.sender) {
+            Ok(next_nonce) if envelope.nonce < next_nonce => {
+                return format!("[Err] Nonce {} was already used", envelope.nonce)
+            }
+            Ok(_) => {}
+            Err(e) => return storage_error(e),
+        }
+    }
+    if let Some(envelope) = &transaction.envelope {
+        if state
+            .pool_state
+            .usage()
+            .is_nonce_pending(&envelope.sender, envelope.nonce)
+        {
+            return format!("[Err] Nonce {} is already pending", envelope.nonce);
+        }
+    }
+    if let Some(governance) = &transaction.governance {
+        let next_height = match state.block_state.height() {
+            Ok(height) => height + 1,
+            Err(e) => return storage_error(e),
+        };
+        if let Err(e) = verify_governance_transaction(
+            governance,
+            &state.genesis.chain_id,
+            &state.consensus_state.validators,
+            state.consensus_state.quorum(),
+            next_height,
+        ) {
+            return format!("[Err] Governance Transaction was rejected: {}", e);
+        }
+    }
+    let entry = PoolEntry {
+        transaction,
+        received_at: get_current_time(),
+        origin: Some(client_address.ip().to_string()),
+        reserved: false,
+    };
+    if let Err(e) = state
+        .mempool_limits
+        .check_admission(state.pool_state.usage(), &entry)
+    {
+        return format!("[Err] Transaction was rejected: {}", e);
+    }
+    let success_response = format!(
+        "[Ok] Transaction is being sequenced: {:?}",
+        &entry.transaction
+    )
+    .to_string();
+    if let Err(e) = state.pool_state.insert_transaction(entry) {
+        return storage_error(e);
+    }
+    // a transaction that is scheduled again after its eviction is pending again
+    state
+        .dropped_transactions
+        .retain(|hash| hash != &transaction_hash);
     success_response
 }
 pub async fn commit(
     Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    headers: HeaderMap,
     Json(commitment): Json<ConsensusCommitment>,
 ) -> String {
     let mut state_lock = shared_state.write().await;
+    if !is_same_network(&headers, &state_lock.genesis.hash_hex()) {
+        return "[Err] Genesis mismatch".to_string();
+    }
     let success_response = format!("[Ok] Commitment was accepted: {:?}", &commitment).to_string();
-    #[cfg(not(feature = "sqlite"))]
-    let last_block_unix_timestamp = state_lock
-        .block_state
-        .get_block_by_height(state_lock.block_state.height - 1)
-        .timestamp;
-    #[cfg(feature = "sqlite")]
-    let last_block_unix_timestamp = state_lock
-        .block_state
-        .get_block_by_height(state_lock.block_state.current_block_height() - 1)
-        .timestamp;
+    let (last_block_unix_timestamp, next_height) = match state_lock.chain_tip() {
+        Ok(chain_tip) => chain_tip,
+        Err(e) => return storage_error(e),
+    };
     if !state_lock.consensus_state.round_winner.is_some() {
         // no round winner found, commitment might be valid
-        let validator = get_committing_validator(
-            last_block_unix_timestamp,
-            state_lock.consensus_state.validators.clone(),
-        );
-        // todo: check if commitment signature is valid for validator
-        if deserialize_vk(&commitment.validator) == validator {
-            let winner =
-                evaluate_commitment(commitment, state_lock.consensus_state.validators.clone());
-            state_lock.consensus_state.round_winner = Some(winner);
+        let validator = state_lock
+            .consensus_state
+            .committing_validator(last_block_unix_timestamp);
+        let round = state_lock.consensus_state.round(last_block_unix_timestamp);
+        if let Err(e) = verify_commitment(&commitment, &validator, next_height, round) {
+            println!(
+                "{}",
+                format_args!("{} Commitment was rejected: {}", "[Warning]".yellow(), e)
+            );
+            return format!("[Err] Commitment was rejected: {}", e);
         }
+        let winner = evaluate_commitment(commitment, state_lock.consensus_state.validators.clone());
+        state_lock.consensus_state.round_winner = Some(winner);
     }
     success_response
 }
+pub async fn timeout(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    headers: HeaderMap,
+    Json(vote): Json<TimeoutVote>,
+) -> String {
+    let mut state_lock = shared_state.write().await;
+    if !is_same_network(&headers, &state_lock.genesis.hash_hex()) {
+        return "[Err] Genesis mismatch".to_string();
+    }
+    let (last_block_unix_timestamp, next_height) = match state_lock.chain_tip() {
+        Ok(chain_tip) => chain_tip,
+        Err(e) => return storage_error(e),
+    };
+    let round = state_lock.consensus_state.round(last_block_unix_timestamp);
+    state_lock.consensus_state.enter_view(next_height, round);
+    if vote.chain_id != state_lock.genesis.chain_id {
+        return "[Err] Timeout Vote is for a different chain".to_string();
+    }
+    if vote.height != next_height || vote.round != round {
+        return "[Warning] Timeout Vote is not for the current round".to_string();
+    }
+    if let Err(e) = verify_timeout_vote(&vote, &state_lock.consensus_state.validators) {
+        return format!("[Err] Timeout Vote was rejected: {}", e);
+    }
+    if let Some(certificate) = state_lock.consensus_state.add_timeout_vote(vote) {
+        println!(
+            "{}",
+            format_args!(
+                "{} Quorum of Timeout Votes for round {}, changing view",
+                "[Warning]".yellow(),
+                certificate.round
+            )
+        );
+        state_lock
+            .consensus_state
+            .apply_view_change(last_block_unix_timestamp);
+        if let Err(e) = state_lock.pool_state.release_reservations() {
+            return storage_error(e);
+        }
+        state_lock
+            .local_gossipper
+            .gossip_timeout_certificate(certificate)
+            .await;
+    }
+    "[Ok] Timeout Vote was accepted".to_string()
+}
+pub async fn view_change(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    headers: HeaderMap,
+    Json(certificate): Json<TimeoutCertificate>,
+) -> String {
+    let mut state_lock = shared_state.write().await;
+    if !is_same_network(&headers, &state_lock.genesis.hash_hex()) {
+        return "[Err] Genesis mismatch".to_string();
+    }
+    let (last_block_unix_timestamp, next_height) = match state_lock.chain_tip() {
+        Ok(chain_tip) => chain_tip,
+        Err(e) => return storage_error(e),
+    };
+    if certificate.chain_id != state_lock.genesis.chain_id {
+        return "[Err] Timeout Certificate is for a different chain".to_string();
+    }
+    // certificates for rounds we already left are ignored
+    if certificate.height != next_height
+        || certificate.round != state_lock.consensus_state.round(last_block_unix_timestamp)
+    {
+        return "[Warning] Timeout Certificate is not for the current round".to_string();
+    }
+    if let Err(e) = verify_timeout_certificate(
+        &certificate,
+        &state_lock.consensus_state.validators,
+        state_lock.consensus_state.quorum(),
+    ) {
+        return format!("[Err] Timeout Certificate was rejected: {}", e);
+    }
+    state_lock
+        .consensus_state
+        .apply_view_change(last_block_unix_timestamp);
+    if let Err(e) = state_lock.pool_state.release_reservations() {
+        return storage_error(e);
+    }
+    println!(
+        "{}",
+        format_args!(
+            "{} View changed after round {}",
+            "[Warning]".yellow(),
+            certificate.round
+        )
+    );
+    "[Ok] View change was applied".to_string()
+}
+pub async fn evidence(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    headers: HeaderMap,
+    Json(evidence): Json<EquivocationEvidence>,
+) -> String {
+    let mut state_lock = shared_state.write().await;
+    if !is_same_network(&headers, &state_lock.genesis.hash_hex()) {
+        return "[Err] Genesis mismatch".to_string();
+    }
+    if let Err(e) = verify_equivocation_evidence(&evidence, &state_lock.consensus_state.validators)
+    {
+        return format!("[Err] Evidence was rejected: {}", e);
+    }
+    // known evidence is not gossipped again
+    match state_lock.block_state.insert_evidence(evidence.clone()) {
+        Ok(true) => {}
+        Ok(false) => return "[Ok] Evidence is already known".to_string(),
+        Err(e) => return storage_error(e),
+    }
+    println!(
+        "{}",
+        format_args!(
+            "{} Received Evidence of equivocation at height {}: {:?}",
+            "[Warning]".yellow(),
+            evidence.height,
+            evidence.validator
+        )
+    );
+    state_lock
+        .local_gossipper
+        .gossip_equivocation_evidence(evidence)
+        .await;
+    "[Ok] Evidence was accepted".to_string()
+}
+// proposals with a missing or malformed signature are rejected before the state is locked
 pub async fn propose(
     Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
-    Json(mut proposal): Json<Block>,
+    headers: HeaderMap,
+    Json(proposal): Json<Block>,
+) -> Result<String, StatusCode> {
+    let signature = match proposal.signature.as_deref().map(Signature::from_slice) {
+        Some(Ok(signature)) => signature,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    Ok(process_proposal(shared_state, headers, proposal, signature).await)
+}
+async fn process_proposal(
+    shared_state: Arc<RwLock<ServerState>>,
+    headers: HeaderMap,
+    mut proposal: Block,
+    signature: Signature,
 ) -> String {
     let mut state_lock = shared_state.write().await;
-    #[cfg(not(feature = "sqlite"))]
-    let last_block_unix_timestamp = state_lock
-        .block_state
-        .get_block_by_height(state_lock.block_state.height - 1)
-        .timestamp;
-    #[cfg(feature = "sqlite")]
-    let last_block_unix_timestamp = state_lock
-        .block_state
-        .get_block_by_height(state_lock.block_state.current_block_height() - 1)
-        .timestamp;
+    if !is_same_network(&headers, &state_lock.genesis.hash_hex()) {
+        return "[Err] Genesis mismatch".to_string();
+    }
+    let (last_block_unix_timestamp, _) = match state_lock.chain_tip() {
+        Ok(chain_tip) => chain_tip,
+        Err(e) => return storage_error(e),
+    };
     let error_response = format!("Block was rejected: {:?}", &proposal).to_string();
-    let round = current_round(last_block_unix_timestamp);
-    if proposal.timestamp < last_block_unix_timestamp + ((round - 1) * (ROUND_DURATION)) {
+    let round_duration = state_lock.consensus_state.round_duration;
+    let round = current_round(last_block_unix_timestamp, round_duration);
+    if proposal.header.timestamp < last_block_unix_timestamp + ((round - 1) * (round_duration)) {
         println!(
             "[Warning] Invalid Proposal Timestamp: {}",
-            proposal.timestamp
+            proposal.header.timestamp
         );
         return error_response;
     };
-    let block_signature = proposal
-        .signature
-        .clone()
-        .expect("Block has not been signed!");
     if let Some(round_winner) = state_lock.consensus_state.round_winner {
-        let signature_deserialized = Signature::from_slice(&block_signature).unwrap();
-        match round_winner.verify(&proposal.to_bytes(), &signature_deserialized) {
+        match round_winner.verify(&proposal.to_bytes(), &signature) {
             Ok(_) => {
                 let res =
                     handle_block_proposal(&mut state_lock, &mut proposal, error_response).await;
                 match res {
-                    Some(e) => return e,
-                    None => {}
+                    Ok(Some(e)) => return e,
+                    Ok(None) => {}
+                    Err(e) => return storage_error(e),
                 }
             }
             Err(_) => {
@@ -112,31 +348,185 @@ pub async fn propose(
         "[Warning] Awaiting consensus evaluation".to_string()
     }
 }
+#[derive(Deserialize)]
+pub struct MerkleProofQuery {
+    height: Option<u32>,
+}
+// without a height the proof is against the latest trie root,
+// with a height it is against the root of that Block and includes its header
 pub async fn merkle_proof(
     Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Query(query): Query<MerkleProofQuery>,
     Json(key): Json<Vec<u8>>,
 ) -> String {
     let mut state_lock = shared_state.write().await;
-    let trie_root = state_lock.merkle_trie_root.clone();
-    let merkle_proof = patricia_trie::merkle::merkle_proof(
-        &mut state_lock.merkle_trie_state,
+    let height = match query.height {
+        Some(height) => height,
+        None => {
+            let trie_root = state_lock.merkle_trie_root.clone();
+            let merkle_proof = patricia_trie::merkle::merkle_proof(
+                &mut StagedTrie::new(state_lock.block_state.as_ref()),
+                key,
+                Node::Root(trie_root),
+            );
+            return match merkle_proof {
+                Some(merkle_proof) => serde_json::to_string(&merkle_proof).unwrap(),
+                None => "[Err] Failed to generate Merkle Proof for Transaction".to_string(),
+            };
+        }
+    };
+    match height_proof(&mut state_lock, key, height) {
+        Ok(height_proof) => serde_json::to_string(&height_proof).unwrap(),
+        Err(response) => response,
+    }
+}
+// a proof against the stored trie root of the Block at this height,
+// the error is the response for the caller
+fn height_proof(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    key: Vec<u8>,
+    height: u32,
+) -> Result<HeightProof, String> {
+    let header = match state_lock.block_state.get_header_by_height(height) {
+        Ok(Some(header)) => header,
+        Ok(None) => return Err("[Warning] Requested Block that does not exist".to_string()),
+        Err(e) => return Err(storage_error(e)),
+    };
+    let trie_root = match state_lock.block_state.get_trie_root_by_height(height) {
+        Ok(Some(trie_root)) => trie_root,
+        Ok(None) => return Err(format!("[Warning] State of Block #{} was pruned", height)),
+        Err(e) => return Err(storage_error(e)),
+    };
+    if trie_root.hash != header.state_root {
+        return Err(format!("[Err] Trie Root does not match Block #{}", height));
+    }
+    match patricia_trie::merkle::merkle_proof(
+        &mut StagedTrie::new(state_lock.block_state.as_ref()),
         key,
         Node::Root(trie_root),
-    );
-    match merkle_proof {
-        Some(merkle_proof) => serde_json::to_string(&merkle_proof).unwrap(),
-        None => "[Err] Failed to generate Merkle Proof for Transaction".to_string(),
+    ) {
+        Some(proof) => Ok(HeightProof { header, proof }),
+        None => Err("[Err] Failed to generate Merkle Proof for Transaction".to_string()),
     }
 }
-pub async fn get_pool(Extension(shared_state): Extension<Arc<RwLock<ServerState>>>) -> String {
-    let state = shared_state.read().await;
-    #[cfg(not(feature = "sqlite"))]
+// status of a Transaction by its hex encoded hash,
+// finalized Transactions include their proof against the state root of their Block
+pub async fn get_transaction_status(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Path(transaction_hash_hex): Path<String>,
+) -> String {
+    let transaction_hash: GenericHash = match hex::decode(&transaction_hash_hex) {
+        Ok(transaction_hash) => transaction_hash,
+        Err(_) => return "[Err] Malformed Transaction hash".to_string(),
+    };
+    let mut state_lock = shared_state.write().await;
+    let status = match state_lock
+        .block_state
+        .get_transaction_location(&transaction_hash)
     {
-        format!("{:?}", state.pool_state.transactions)
+        Ok(Some((height, position))) => {
+            // the trie only grows, so the latest root proves Transactions of pruned Blocks
+            let proof_height = match state_lock.block_state.get_trie_root_by_height(height) {
+                Ok(Some(_)) => height,
+                Ok(None) => match state_lock.block_state.height() {
+                    Ok(latest_height) => latest_height,
+                    Err(e) => return storage_error(e),
+                },
+                Err(e) => return storage_error(e),
+            };
+            let key = transaction_key(&transaction_hash);
+            match height_proof(&mut state_lock, key, proof_height) {
+                Ok(proof) => TransactionStatus::Finalized {
+                    height,
+                    position,
+                    proof,
+                },
+                Err(response) => return response,
+            }
+        }
+        Ok(None) => {
+            let included =
+                state_lock
+                    .consensus_state
+                    .pending_block
+                    .as_ref()
+                    .and_then(|pending_block| {
+                        pending_block
+                            .transactions
+                            .iter()
+                            .position(|transaction| transaction.hash() == transaction_hash)
+                            .map(|position| (pending_block.header.height, position as u32))
+                    });
+            match included {
+                Some((height, position)) => TransactionStatus::Included { height, position },
+                None => match state_lock
+                    .pool_state
+                    .contains_transaction(&transaction_hash)
+                {
+                    Ok(true) => TransactionStatus::Pending,
+                    Ok(false) if state_lock.dropped_transactions.contains(&transaction_hash) => {
+                        TransactionStatus::Dropped
+                    }
+                    Ok(false) => return "[Warning] Unknown Transaction".to_string(),
+                    Err(e) => return storage_error(e),
+                },
+            }
+        }
+        Err(e) => return storage_error(e),
+    };
+    serde_json::to_string(&status).unwrap()
+}
+// proves that the Transaction with this hash was not sequenced up to the Block,
+// without a height the proof is against the latest Block
+pub async fn exclusion_proof(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Query(query): Query<MerkleProofQuery>,
+    Json(transaction_hash): Json<GenericHash>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    let height = match query.height {
+        Some(height) => height,
+        None => match state_lock.block_state.height() {
+            Ok(height) => height,
+            Err(e) => return storage_error(e),
+        },
+    };
+    let header = match state_lock.block_state.get_header_by_height(height) {
+        Ok(Some(header)) => header,
+        Ok(None) => return "[Warning] Requested Block that does not exist".to_string(),
+        Err(e) => return storage_error(e),
+    };
+    let latest_height = match state_lock.block_state.height() {
+        Ok(latest_height) => latest_height,
+        Err(e) => return storage_error(e),
+    };
+    // the tree of the latest Block is kept in memory, earlier ones are rebuilt
+    let rebuilt_tree;
+    let sequenced_tree = if height == latest_height {
+        &state_lock.sequenced_tree
+    } else {
+        rebuilt_tree = match state_lock.block_state.get_sequenced_leaves(height) {
+            Ok(leaves) => SequencedTree::from_leaves(&leaves),
+            Err(e) => return storage_error(e),
+        };
+        &rebuilt_tree
+    };
+    if sequenced_tree.root() != header.sequenced_root {
+        return format!(
+            "[Err] Sequenced Transactions do not match Block #{}",
+            height
+        );
     }
-    #[cfg(feature = "sqlite")]
-    {
-        format!("{:?}", state.pool_state.get_all_transactions())
+    match sequenced_tree.exclusion_proof(&transaction_hash) {
+        Some(proof) => serde_json::to_string(&HeightExclusionProof { header, proof }).unwrap(),
+        None => format!("[Err] Transaction was sequenced up to Block #{}", height),
+    }
+}
+pub async fn get_pool(Extension(shared_state): Extension<Arc<RwLock<ServerState>>>) -> String {
+    let state = shared_state.read().await;
+    match state.pool_state.get_all_transactions() {
+        Ok(transactions) => format!("{:?}", transactions),
+        Err(e) => storage_error(e),
     }
 }
 pub async fn get_commitments(
@@ -154,19 +544,77 @@ pub async fn get_block(
         "{}",
         format_args!("{} Peer Requested Block #{}", "[Info]".green(), height)
     );
-    #[cfg(not(feature = "sqlite"))]
-    let previous_block_height = state_lock.block_state.height - 1;
-    #[cfg(feature = "sqlite")]
-    let previous_block_height = state_lock.block_state.current_block_height();
-    if previous_block_height < height + 1 {
-        "[Warning] Requested Block that does not exist".to_string()
-    } else {
-        match serde_json::to_string(&state_lock.block_state.get_block_by_height(height)) {
+    match state_lock.block_state.get_block_by_height(height) {
+        Ok(Some(block)) => match serde_json::to_string(&block) {
             Ok(block_json) => block_json,
             Err(e) => e.to_string(),
-        }
+        },
+        Ok(None) => "[Warning] Requested Block that does not exist".to_string(),
+        Err(e) => storage_error(e),
+    }
+}
+// headers are served for pruned Blocks as well
+pub async fn get_header(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Path(height): Path<u32>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    match state_lock.block_state.get_header_by_height(height) {
+        Ok(Some(header)) => serde_json::to_string(&header).unwrap(),
+        Ok(None) => "[Warning] Requested Header that does not exist".to_string(),
+        Err(e) => storage_error(e),
     }
 }
+// consecutive finalized Blocks with their certificates, used by synchronizing peers.
+// the range is capped and ends early at the tip
+pub async fn get_blocks(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Path((from, to)): Path<(u32, u32)>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    let to = to.min(from.saturating_add(SYNC_RANGE_SIZE - 1));
+    let mut certified_blocks: Vec<CertifiedBlock> = Vec::new();
+    for height in from..=to {
+        let block = match state_lock.block_state.get_block_by_height(height) {
+            Ok(Some(block)) => block,
+            Ok(None) => break,
+            Err(e) => return storage_error(e),
+        };
+        // Blocks without a certificate can't be verified by the peer
+        let certificate = match state_lock.block_state.get_certificate_by_height(height) {
+            Ok(Some(certificate)) => certificate,
+            Ok(None) => break,
+            Err(e) => return storage_error(e),
+        };
+        certified_blocks.push(CertifiedBlock { block, certificate });
+    }
+    serde_json::to_string(&certified_blocks).unwrap()
+}
+pub async fn get_certificate(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Path(height): Path<u32>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    match state_lock.block_state.get_certificate_by_height(height) {
+        Ok(Some(certificate)) => serde_json::to_string(&certificate).unwrap(),
+        Ok(None) => "[Warning] Requested Certificate that does not exist".to_string(),
+        Err(e) => storage_error(e),
+    }
+}
+pub async fn get_evidence(Extension(shared_state): Extension<Arc<RwLock<ServerState>>>) -> String {
+    let state_lock = shared_state.read().await;
+    match state_lock.block_state.get_evidence() {
+        Ok(evidence) => serde_json::to_string(&evidence).unwrap(),
+        Err(e) => storage_error(e),
+    }
+}
+pub async fn get_validators(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Path(height): Path<u32>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    serde_json::to_string(&state_lock.consensus_state.validator_set_at(height)).unwrap()
+}
 pub async fn get_state_root_hash(
     Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
 ) -> String {
@@ -178,9 +626,59 @@ pub async fn get_state_root_hash(
 }
 pub async fn get_height(Extension(shared_state): Extension<Arc<RwLock<ServerState>>>) -> String {
     let state_lock = shared_state.read().await;
-    #[cfg(not(feature = "sqlite"))]
-    let previous_block_height = state_lock.block_state.height - 1;
-    #[cfg(feature = "sqlite")]
-    let previous_block_height = state_lock.block_state.current_block_height();
-    serde_json::to_string(&previous_block_height).unwrap()
+    match state_lock.block_state.height() {
+        Ok(height) => serde_json::to_string(&height).unwrap(),
+        Err(e) => storage_error(e),
+    }
+}
+pub async fn get_status(Extension(shared_state): Extension<Arc<RwLock<ServerState>>>) -> String {
+    let state_lock = shared_state.read().await;
+    let tip = match state_lock.block_state.latest_block() {
+        Ok(tip) => tip,
+        Err(e) => return storage_error(e),
+    };
+    let pruned_height = match state_lock.block_state.get_base_height() {
+        Ok(pruned_height) => pruned_height,
+        Err(e) => return storage_error(e),
+    };
+    serde_json::to_string(&NodeStatus {
+        chain_id: state_lock.genesis.chain_id.clone(),
+        genesis_hash: state_lock.genesis.hash_hex(),
+        height: tip.header.height,
+        tip_hash: tip.hash(),
+        snapshot_height: state_lock
+            .snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.manifest.certified_block.block.header.height),
+        pruned_height,
+    })
+    .unwrap()
+}
+// the chunks of the latest snapshot can be fetched by index at /get/snapshot/:index
+pub async fn get_snapshot_manifest(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    match &state_lock.snapshot {
+        Some(snapshot) => serde_json::to_string(&snapshot.manifest).unwrap(),
+        None => "[Warning] No snapshot available".to_string(),
+    }
+}
+pub async fn get_snapshot_chunk(
+    Extension(shared_state): Extension<Arc<RwLock<ServerState>>>,
+    Path(index): Path<u32>,
+) -> String {
+    let state_lock = shared_state.read().await;
+    match state_lock
+        .snapshot
+        .as_ref()
+        .and_then(|snapshot| snapshot.chunks.get(index as usize))
+    {
+        Some(chunk) => serde_json::to_string(chunk).unwrap(),
+        None => "[Warning] Requested snapshot chunk that does not exist".to_string(),
+    }
+}
+pub async fn get_genesis(Extension(shared_state): Extension<Arc<RwLock<ServerState>>>) -> String {
+    let state_lock = shared_state.read().await;
+    serde_json::to_string(&state_lock.genesis.hash_hex()).unwrap()
 }
@@ -0,0 +1,294 @@
+use crate::consensus::logic::{verify_snapshot_chunk, verify_snapshot_manifest};
+use crate::handlers::{apply_certified_block, apply_snapshot};
+use crate::state::snapshot::{build_snapshot, snapshot_height};
+use crate::types::{CertifiedBlock, SnapshotChunk, SnapshotManifest};
+use crate::ServerState;
+use anyhow::{anyhow, bail, Context, Result};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+
+// an archive starts with the magic bytes and the format version, followed by
+// records that are each prefixed with their length as a little endian u32
+const ARCHIVE_MAGIC: &[u8; 8] = b"SEQARCHV";
+const ARCHIVE_VERSION: u32 = 1;
+// larger records are rejected before anything is allocated for them
+const MAX_RECORD_BYTES: u32 = 256_000_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveHeader {
+    pub chain_id: String,
+    // hex encoded
+    pub genesis_hash: String,
+    // height of the latest Block in the archive
+    pub height: u32,
+}
+// records are JSON encoded so that archives don't depend on the storage backend,
+// the snapshot comes before the Blocks that follow it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ArchiveRecord {
+    Header(ArchiveHeader),
+    SnapshotManifest(SnapshotManifest),
+    SnapshotChunk(SnapshotChunk),
+    Block(CertifiedBlock),
+    // marks a complete archive, an archive without it was truncated
+    End,
+}
+pub fn write_preamble(writer: &mut impl Write) -> Result<()> {
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+    Ok(())
+}
+pub fn read_preamble(reader: &mut impl Read) -> Result<()> {
+    let mut magic = [0u8; 8];
+    reader
+        .read_exact(&mut magic)
+        .context("Archive is too short")?;
+    if &magic != ARCHIVE_MAGIC {
+        bail!("Not a chain archive");
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != ARCHIVE_VERSION {
+        bail!("Unsupported archive version: {}", version);
+    }
+    Ok(())
+}
+pub fn write_record(writer: &mut impl Write, record: &ArchiveRecord) -> Result<()> {
+    let record_json = serde_json::to_vec(record)?;
+    let length = u32::try_from(record_json.len())
+        .ok()
+        .filter(|length| *length <= MAX_RECORD_BYTES)
+        .ok_or_else(|| anyhow!("Archive record is too large: {} bytes", record_json.len()))?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(&record_json)?;
+    Ok(())
+}
+pub fn read_record(reader: &mut impl Read) -> Result<ArchiveRecord> {
+    let mut length = [0u8; 4];
+    reader
+        .read_exact(&mut length)
+        .context("Archive is truncated")?;
+    let length = u32::from_le_bytes(length);
+    if length > MAX_RECORD_BYTES {
+        bail!("Archive record is too large: {} bytes", length);
+    }
+    let mut record_json = vec![0u8; length as usize];
+    reader
+        .read_exact(&mut record_json)
+        .context("Archive is truncated")?;
+    Ok(serde_json::from_slice(&record_json)?)
+}
+// writes every stored Block with its certificate. with `include_snapshot` the Blocks up
+// to the latest snapshot height are replaced by the snapshot, which is required for
+// nodes that bootstrapped from a snapshot themselves
+pub async fn export_chain(
+    state: Arc<RwLock<ServerState>>,
+    path: &str,
+    include_snapshot: bool,
+) -> Result<()> {
+    let state_lock = state.read().await;
+    let height = state_lock.block_state.height()?;
+    let snapshot = match (include_snapshot, snapshot_height(height)) {
+        (true, Some(snapshot_height)) => Some(build_snapshot(
+            state_lock.block_state.as_ref(),
+            snapshot_height,
+        )?),
+        _ => None,
+    };
+    let first_height = match &snapshot {
+        Some(snapshot) => snapshot.manifest.certified_block.block.header.height + 1,
+        None => {
+            if let Some(base_height) = state_lock.block_state.get_base_height()? {
+                bail!(
+                    "Blocks below height {} are not stored, export with --snapshot",
+                    base_height
+                );
+            }
+            1
+        }
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_preamble(&mut writer)?;
+    write_record(
+        &mut writer,
+        &ArchiveRecord::Header(ArchiveHeader {
+            chain_id: state_lock.genesis.chain_id.clone(),
+            genesis_hash: state_lock.genesis.hash_hex(),
+            height,
+        }),
+    )?;
+    if let Some(snapshot) = snapshot {
+        write_record(
+            &mut writer,
+            &ArchiveRecord::SnapshotManifest(snapshot.manifest),
+        )?;
+        for chunk in snapshot.chunks {
+            write_record(&mut writer, &ArchiveRecord::SnapshotChunk(chunk))?;
+        }
+    }
+    for block_height in first_height..=height {
+        let block = state_lock
+            .block_state
+            .get_block_by_height(block_height)?
+            .ok_or_else(|| anyhow!("Block is missing: {}", block_height))?;
+        let certificate = state_lock
+            .block_state
+            .get_certificate_by_height(block_height)?
+            .ok_or_else(|| anyhow!("Certificate is missing: {}", block_height))?;
+        write_record(
+            &mut writer,
+            &ArchiveRecord::Block(CertifiedBlock { block, certificate }),
+        )?;
+    }
+    write_record(&mut writer, &ArchiveRecord::End)?;
+    writer.flush()?;
+    println!(
+        "{}",
+        format_args!(
+            "{} Exported Blocks {} to {} to {}",
+            "[Info]".green(),
+            first_height,
+            height,
+            path
+        )
+    );
+    Ok(())
+}
+// every Block is verified like a synchronized Block before it is stored, Blocks the
+// node already has are skipped. a snapshot is applied if it is ahead of the local tip,
+// otherwise it has to match the local Block at its height
+pub async fn import_chain(state: Arc<RwLock<ServerState>>, path: &str) -> Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_preamble(&mut reader)?;
+    let mut state_lock = state.write().await;
+    let header = match read_record(&mut reader)? {
+        ArchiveRecord::Header(header) => header,
+        _ => bail!("Archive does not start with a header"),
+    };
+    if header.chain_id != state_lock.genesis.chain_id
+        || header.genesis_hash != state_lock.genesis.hash_hex()
+    {
+        bail!(
+            "Archive was exported from chain {} with a different genesis",
+            header.chain_id
+        );
+    }
+    println!(
+        "{}",
+        format_args!(
+            "{} Importing archive up to height {}",
+            "[Info]".green(),
+            header.height
+        )
+    );
+    let mut manifest: Option<SnapshotManifest> = None;
+    let mut chunks: Vec<SnapshotChunk> = Vec::new();
+    let mut imported: u32 = 0;
+    loop {
+        let record = read_record(&mut reader)?;
+        // the snapshot is complete once the first Block or the end follows it
+        if let (Some(_), ArchiveRecord::Block(_) | ArchiveRecord::End) = (&manifest, &record) {
+            let manifest = manifest.take().unwrap();
+            if manifest.chunk_count as usize != chunks.len() {
+                bail!("Snapshot in the archive is incomplete");
+            }
+            let snapshot_header = &manifest.certified_block.block.header;
+            let local_height = state_lock.block_state.height()?;
+            if snapshot_header.height > local_height {
+                if !apply_snapshot(&mut state_lock, manifest, std::mem::take(&mut chunks))? {
+                    bail!("Snapshot in the archive is invalid");
+                }
+            } else {
+                // the local chain already covers the snapshot, it has to be the same chain
+                let local_header = state_lock
+                    .block_state
+                    .get_header_by_height(snapshot_header.height)?;
+                if local_header.map(|header| header.hash()) != Some(snapshot_header.hash()) {
+                    bail!(
+                        "Snapshot in the archive does not match the local Block #{}",
+                        snapshot_header.height
+                    );
+                }
+            }
+        }
+        match record {
+            ArchiveRecord::Header(_) => bail!("Archive contains a second header"),
+            ArchiveRecord::SnapshotManifest(snapshot_manifest) => {
+                verify_snapshot_manifest(&snapshot_manifest)?;
+                manifest = Some(snapshot_manifest);
+            }
+            ArchiveRecord::SnapshotChunk(chunk) => {
+                let snapshot_manifest = manifest
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Snapshot chunk without a manifest"))?;
+                if chunk.index as usize != chunks.len() {
+                    bail!("Snapshot chunk {} is out of order", chunk.index);
+                }
+                verify_snapshot_chunk(&chunk, snapshot_manifest)?;
+                chunks.push(chunk);
+            }
+            ArchiveRecord::Block(certified_block) => {
+                let height = certified_block.block.header.height;
+                if height <= state_lock.block_state.height()? {
+                    continue;
+                }
+                if !apply_certified_block(&mut state_lock, certified_block)? {
+                    bail!("Block {} in the archive is invalid", height);
+                }
+                imported += 1;
+            }
+            ArchiveRecord::End => break,
+        }
+    }
+    println!(
+        "{}",
+        format_args!(
+            "{} Imported {} Blocks, height is {}",
+            "[Info]".green(),
+            imported,
+            state_lock.block_state.height()?
+        )
+    );
+    Ok(())
+}
+
+#[test]
+fn test_archive_records() {
+    use std::io::Cursor;
+    let header = ArchiveHeader {
+        chain_id: "test".to_string(),
+        genesis_hash: "00".to_string(),
+        height: 3,
+    };
+    let mut archive: Vec<u8> = Vec::new();
+    write_preamble(&mut archive).unwrap();
+    write_record(&mut archive, &ArchiveRecord::Header(header.clone())).unwrap();
+    write_record(&mut archive, &ArchiveRecord::End).unwrap();
+    let mut reader = Cursor::new(archive.clone());
+    read_preamble(&mut reader).unwrap();
+    match read_record(&mut reader).unwrap() {
+        ArchiveRecord::Header(read_header) => assert_eq!(read_header, header),
+        _ => panic!("expected the header"),
+    }
+    assert!(matches!(
+        read_record(&mut reader).unwrap(),
+        ArchiveRecord::End
+    ));
+    // a truncated archive is detected
+    let mut reader = Cursor::new(archive[..archive.len() - 2].to_vec());
+    read_preamble(&mut reader).unwrap();
+    read_record(&mut reader).unwrap();
+    assert!(read_record(&mut reader).is_err());
+    // other versions are rejected
+    let mut other_version = archive.clone();
+    other_version[8] = 2;
+    assert!(read_preamble(&mut Cursor::new(other_version)).is_err());
+    assert!(read_preamble(&mut Cursor::new(b"database".to_vec())).is_err());
+}
@@ -0,0 +1,230 @@
+use crate::crypto::ecdsa::{deserialize_sk, deserialize_vk};
+use crate::exclusion::sequenced_root;
+use crate::get_current_time;
+use crate::types::{
+    transactions_root, Block, BlockHeader, GenericHash, GenericPublicKey, Timestamp,
+};
+use anyhow::{bail, Context, Result};
+use k256::ecdsa::{SigningKey, VerifyingKey};
+use k256::sha2::{Digest, Sha256};
+use patricia_trie::store::types::Root;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+// on-disk representation of the genesis file,
+// validator keys are SEC1 encoded
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisConfig {
+    pub chain_id: String,
+    pub timestamp: Timestamp,
+    // root hash of the trie before the first block,
+    // None for an empty trie
+    pub state_root: Option<GenericHash>,
+    pub validators: Vec<GenericPublicKey>,
+    // a fixed number of commitments required for finality,
+    // when omitted the BFT quorum of the validator set applies
+    #[serde(default)]
+    pub consensus_threshold: Option<u32>,
+    // time before a new block is created, from a block's timestamp onwards
+    // specified in seconds
+    pub round_duration: u32,
+    pub clearing_phase_duration: u32,
+    // trusted deployments may accept raw transactions without a sender signature
+    #[serde(default)]
+    pub require_signed_transactions: bool,
+    // upper bound for the serialized size of the transactions in a Block
+    #[serde(default = "default_max_block_bytes")]
+    pub max_block_bytes: u64,
+}
+fn default_max_block_bytes() -> u64 {
+    5_000_000
+}
+
+#[derive(Debug, Clone)]
+pub struct Genesis {
+    pub chain_id: String,
+    pub timestamp: Timestamp,
+    // sha256 over the bincode serialized genesis config,
+    // nodes only talk to peers with the same genesis hash
+    pub hash: GenericHash,
+    pub validators: Vec<VerifyingKey>,
+    pub consensus_threshold: Option<u32>,
+    pub round_duration: u32,
+    pub clearing_phase_duration: u32,
+    pub require_signed_transactions: bool,
+    pub max_block_bytes: u64,
+}
+
+impl Genesis {
+    pub fn load(path: &str) -> Result<Self> {
+        let genesis_json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read genesis file: {}", path))?;
+        let config: GenesisConfig = serde_json::from_str(&genesis_json)
+            .with_context(|| format!("Malformed genesis file: {}", path))?;
+        Self::from_config(config)
+    }
+    pub fn from_config(config: GenesisConfig) -> Result<Self> {
+        if config.chain_id.is_empty() {
+            bail!("Genesis does not specify a chain id");
+        }
+        // rounds are counted from the genesis timestamp onwards
+        if config.timestamp > get_current_time() {
+            bail!("Genesis timestamp {} is in the future", config.timestamp);
+        }
+        // the network starts without state, the first Block is applied to an empty trie
+        if config
+            .state_root
+            .as_ref()
+            .is_some_and(|state_root| Some(state_root) != Root::empty().hash.as_ref())
+        {
+            bail!("Genesis state root must be the root of an empty trie");
+        }
+        if config.validators.is_empty() {
+            bail!("Genesis does not contain any validators");
+        }
+        let mut validators: Vec<VerifyingKey> = Vec::new();
+        for (index, validator) in config.validators.iter().enumerate() {
+            let validator = deserialize_vk(validator)
+                .with_context(|| format!("Invalid key for validator #{}", index))?;
+            if validators.contains(&validator) {
+                bail!("Duplicate key for validator #{}", index);
+            }
+            validators.push(validator);
+        }
+        if let Some(consensus_threshold) = config.consensus_threshold {
+            if consensus_threshold == 0 {
+                bail!("Consensus threshold must be at least 1");
+            }
+            if consensus_threshold > validators.len() as u32 {
+                bail!(
+                    "Consensus threshold {} exceeds the number of validators {}",
+                    consensus_threshold,
+                    validators.len()
+                );
+            }
+        }
+        if config.max_block_bytes == 0 {
+            bail!("Block size limit must be at least 1 byte");
+        }
+        if config.round_duration == 0 {
+            bail!("Round duration must be at least 1 second");
+        }
+        if config.clearing_phase_duration >= config.round_duration {
+            bail!(
+                "Clearing phase duration {} must be shorter than the round duration {}",
+                config.clearing_phase_duration,
+                config.round_duration
+            );
+        }
+        let hash = Sha256::digest(bincode::serialize(&config).unwrap()).to_vec();
+        Ok(Self {
+            chain_id: config.chain_id,
+            timestamp: config.timestamp,
+            hash,
+            validators,
+            consensus_threshold: config.consensus_threshold,
+            round_duration: config.round_duration,
+            clearing_phase_duration: config.clearing_phase_duration,
+            require_signed_transactions: config.require_signed_transactions,
+            max_block_bytes: config.max_block_bytes,
+        })
+    }
+    pub fn hash_hex(&self) -> String {
+        hex::encode(&self.hash)
+    }
+    // identical on every node of the network, the chain starts with an empty trie
+    pub fn block(&self) -> Block {
+        Block {
+            header: BlockHeader {
+                height: 0,
+                round: 0,
+                chain_id: self.chain_id.clone(),
+                parent_hash: self.hash.clone(),
+                transactions_root: transactions_root(&[]),
+                state_root: Root::empty().hash,
+                sequenced_root: sequenced_root(&[]),
+                proposer: vec![],
+                timestamp: self.timestamp,
+            },
+            signature: Some(vec![]),
+            transactions: vec![],
+            commitments: None,
+        }
+    }
+}
+
+// the key file holds the serialized secret key as a JSON byte array
+pub fn load_signing_key(path: &str) -> Result<SigningKey> {
+    let key_json =
+        fs::read_to_string(path).with_context(|| format!("Failed to read key file: {}", path))?;
+    let key_serialized: Vec<u8> =
+        serde_json::from_str(&key_json).with_context(|| format!("Malformed key file: {}", path))?;
+    deserialize_sk(&key_serialized).with_context(|| format!("Invalid signing key in: {}", path))
+}
+
+#[cfg(test)]
+fn test_config(validator_count: usize, consensus_threshold: Option<u32>) -> GenesisConfig {
+    use crate::crypto::ecdsa::Keypair;
+    GenesisConfig {
+        chain_id: "test".to_string(),
+        timestamp: 0,
+        state_root: None,
+        validators: (0..validator_count)
+            .map(|_| Keypair::new().serialize_vk())
+            .collect(),
+        consensus_threshold,
+        round_duration: 120,
+        clearing_phase_duration: 10,
+        require_signed_transactions: false,
+        max_block_bytes: default_max_block_bytes(),
+    }
+}
+#[test]
+fn test_load_bundled_genesis() {
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    assert_eq!(genesis.validators.len(), 4);
+    let signing_key = load_signing_key("resources/keys/v1.json").unwrap();
+    assert_eq!(genesis.validators[0], VerifyingKey::from(&signing_key));
+}
+#[test]
+fn test_reject_duplicate_validator() {
+    let mut config = test_config(2, None);
+    config.validators[1] = config.validators[0].clone();
+    assert!(Genesis::from_config(config).is_err());
+}
+#[test]
+fn test_reject_threshold_above_validator_count() {
+    assert!(Genesis::from_config(test_config(2, Some(3))).is_err());
+    assert!(Genesis::from_config(test_config(3, Some(3))).is_ok());
+    assert!(Genesis::from_config(test_config(2, None)).is_ok());
+}
+#[test]
+fn test_reject_malformed_validator_key() {
+    let mut config = test_config(2, None);
+    config.validators[1] = vec![0; 33];
+    assert!(Genesis::from_config(config).is_err());
+}
+#[test]
+fn test_genesis_hash_depends_on_chain_id() {
+    let config = test_config(2, None);
+    let mut other_config = config.clone();
+    other_config.chain_id = "other".to_string();
+    let genesis = Genesis::from_config(config.clone()).unwrap();
+    assert_eq!(genesis.hash, Genesis::from_config(config).unwrap().hash);
+    assert_ne!(
+        genesis.hash,
+        Genesis::from_config(other_config).unwrap().hash
+    );
+}
+#[test]
+fn test_reject_future_timestamp() {
+    let mut config = test_config(2, None);
+    config.timestamp = get_current_time() + 3_600;
+    assert!(Genesis::from_config(config).is_err());
+}
+#[test]
+fn test_reject_initial_state() {
+    let mut config = test_config(2, None);
+    config.state_root = Some(vec![1; 32]);
+    assert!(Genesis::from_config(config).is_err());
+}
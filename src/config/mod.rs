@@ -1,2 +1,2 @@
-pub mod consensus;
+pub mod genesis;
 pub mod network;
@@ -1,9 +1,93 @@
-use lazy_static::lazy_static;
-lazy_static! {
-    pub static ref PEERS: Vec<&'static str> = vec![
-        "rust-node-1:8080",
-        "rust-node-2:8081",
-        "rust-node-3:8082",
-        "rust-node-4:8083"
+use crate::crypto::ecdsa::deserialize_vk;
+use crate::gossipper::Peer;
+use crate::types::GenericPublicKey;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+
+// on-disk representation of a peer, the validator key is SEC1 encoded
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerConfig {
+    pub address: String,
+    pub validator: GenericPublicKey,
+}
+
+// where the peers list is read from, `--peers` takes precedence over `PEERS`,
+// both hold a peers list in the format of the peers file
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerSource {
+    Inline(String),
+    File(String),
+}
+impl PeerSource {
+    // removes `--peers <json>` from the command line arguments
+    pub fn from_args(args: &mut Vec<String>) -> Result<Self> {
+        if let Some(index) = args.iter().position(|arg| arg == "--peers") {
+            if index + 1 >= args.len() {
+                bail!("--peers requires a peers list");
+            }
+            let peers_json = args.remove(index + 1);
+            args.remove(index);
+            return Ok(Self::Inline(peers_json));
+        }
+        if let Ok(peers_json) = env::var("PEERS") {
+            return Ok(Self::Inline(peers_json));
+        }
+        Ok(Self::File(
+            env::var("PATH_TO_PEERS").unwrap_or("resources/peers.json".to_string()),
+        ))
+    }
+    pub fn load(&self) -> Result<Vec<Peer>> {
+        match self {
+            Self::Inline(peers_json) => parse_peers(peers_json).context("Malformed peers list"),
+            Self::File(path) => load_peers(path),
+        }
+    }
+}
+
+pub fn load_peers(path: &str) -> Result<Vec<Peer>> {
+    let peers_json =
+        fs::read_to_string(path).with_context(|| format!("Failed to read peers file: {}", path))?;
+    parse_peers(&peers_json).with_context(|| format!("Malformed peers file: {}", path))
+}
+
+pub fn parse_peers(peers_json: &str) -> Result<Vec<Peer>> {
+    let peer_configs: Vec<PeerConfig> = serde_json::from_str(peers_json)?;
+    let mut peers: Vec<Peer> = Vec::new();
+    for peer_config in peer_configs {
+        let validator = deserialize_vk(&peer_config.validator)
+            .with_context(|| format!("Invalid key for peer: {}", peer_config.address))?;
+        if peers.iter().any(|peer| peer.address == peer_config.address) {
+            bail!("Duplicate peer address: {}", peer_config.address);
+        }
+        peers.push(Peer {
+            address: peer_config.address,
+            validator,
+        });
+    }
+    Ok(peers)
+}
+
+#[test]
+fn test_load_bundled_peers() {
+    let peers = load_peers("resources/peers.json").unwrap();
+    assert_eq!(peers.len(), 4);
+    assert_eq!(peers[0].address, "rust-node-1:8080");
+}
+
+#[test]
+fn test_peers_from_args() {
+    let peers_json = std::fs::read_to_string("resources/peers.json").unwrap();
+    let mut args = vec![
+        "node".to_string(),
+        "--peers".to_string(),
+        peers_json.clone(),
+        "import".to_string(),
     ];
+    let source = PeerSource::from_args(&mut args).unwrap();
+    assert_eq!(source, PeerSource::Inline(peers_json));
+    assert_eq!(args, vec!["node".to_string(), "import".to_string()]);
+    assert_eq!(source.load().unwrap().len(), 4);
+    let mut args = vec!["node".to_string(), "--peers".to_string()];
+    assert!(PeerSource::from_args(&mut args).is_err());
 }
@@ -1,6 +1,14 @@
-use crate::types::ConsensusCommitment;
-use crate::{config::consensus::ROUND_DURATION, get_current_time};
-use k256::ecdsa::VerifyingKey;
+use crate::consensus::zrand::verify_receipt;
+use crate::crypto::ecdsa::deserialize_vk;
+use crate::exclusion::verify_membership;
+use crate::get_current_time;
+use crate::types::{
+    Block, ConsensusCommitment, EquivocationEvidence, FinalityCertificate, GenericPublicKey,
+    GenericSignature, GovernanceTransaction, SignedHeader, SnapshotChunk, SnapshotManifest,
+    TimeoutCertificate, TimeoutVote, Transaction, ValidatorSetChange,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use k256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
 use num_bigint::BigInt;
 use num_traits::ToPrimitive;
 use zk_logic::{random_bytes_to_int, types::CircuitOutputs};
@@ -14,20 +22,636 @@ pub fn evaluate_commitment(
         validators,
     )
 }
-pub fn get_committing_validator(
-    last_block_unix_timestamp: u32,
-    validators: Vec<VerifyingKey>,
-) -> VerifyingKey {
-    let round = current_round(last_block_unix_timestamp) - 1;
+// a commitment is only valid for the expected validator at the current height and round,
+// the signature binds the receipt's journal to both. the random number must be generated
+// from the validator's key and the height, so that a committer can't pick its inputs
+pub fn verify_commitment(
+    commitment: &ConsensusCommitment,
+    expected_validator: &VerifyingKey,
+    height: u32,
+    round: u32,
+) -> Result<()> {
+    let validator = deserialize_vk(&commitment.validator)?;
+    if &validator != expected_validator {
+        bail!("Commitment is not from the committing validator");
+    }
+    if commitment.height != height || commitment.round != round {
+        bail!(
+            "Commitment is for height {} round {}, expected height {} round {}",
+            commitment.height,
+            commitment.round,
+            height,
+            round
+        );
+    }
+    let signature = Signature::from_slice(&commitment.signature)
+        .map_err(|_| anyhow!("Malformed Commitment Signature"))?;
+    validator
+        .verify(&commitment.to_bytes(), &signature)
+        .map_err(|_| anyhow!("Invalid Commitment Signature"))?;
+    let circuit_outputs = verify_receipt(&commitment.receipt)?;
+    if circuit_outputs.public_key.as_slice() != &*expected_validator.to_sec1_bytes()
+        || circuit_outputs.nonce.as_slice() != height.to_be_bytes()
+    {
+        bail!("Commitment Receipt was generated for different inputs");
+    }
+    Ok(())
+}
+pub fn verify_timeout_vote(
+    vote: &TimeoutVote,
+    validators: &[VerifyingKey],
+) -> Result<VerifyingKey> {
+    let validator = deserialize_vk(&vote.validator)?;
+    if !validators.contains(&validator) {
+        bail!("Timeout Vote from unknown validator");
+    }
+    let signature = Signature::from_slice(&vote.signature)
+        .map_err(|_| anyhow!("Malformed Timeout Vote Signature"))?;
+    validator
+        .verify(&vote.to_bytes(), &signature)
+        .map_err(|_| anyhow!("Invalid Timeout Vote Signature"))?;
+    Ok(validator)
+}
+// verifies every signature over the message and returns the number of distinct validators
+// that signed it, a validator that signed more than once is only counted once
+pub fn count_distinct_signers<'a>(
+    signatures: impl IntoIterator<Item = (&'a GenericPublicKey, &'a GenericSignature)>,
+    message: &[u8],
+    validators: &[VerifyingKey],
+) -> Result<u32> {
+    let mut signers: Vec<VerifyingKey> = Vec::new();
+    for (validator, signature) in signatures {
+        let validator = deserialize_vk(validator)?;
+        if !validators.contains(&validator) {
+            bail!("Signature from unknown validator");
+        }
+        let signature =
+            Signature::from_slice(signature).map_err(|_| anyhow!("Malformed Signature"))?;
+        validator
+            .verify(message, &signature)
+            .map_err(|_| anyhow!("Invalid Signature"))?;
+        if !signers.contains(&validator) {
+            signers.push(validator);
+        }
+    }
+    Ok(signers.len() as u32)
+}
+// a certificate needs votes of a quorum of distinct validators
+// for the same chain, height and round
+pub fn verify_timeout_certificate(
+    certificate: &TimeoutCertificate,
+    validators: &[VerifyingKey],
+    consensus_threshold: u32,
+) -> Result<()> {
+    if certificate.votes.iter().any(|vote| {
+        vote.chain_id != certificate.chain_id
+            || vote.height != certificate.height
+            || vote.round != certificate.round
+    }) {
+        bail!("Timeout Vote for a different chain, height or round");
+    }
+    let signers = count_distinct_signers(
+        certificate
+            .votes
+            .iter()
+            .map(|vote| (&vote.validator, &vote.signature)),
+        &certificate.to_bytes(),
+        validators,
+    )
+    .context("Invalid Timeout Vote")?;
+    if signers < consensus_threshold {
+        bail!(
+            "Timeout Certificate lacks a quorum: {} of {} votes",
+            signers,
+            consensus_threshold
+        );
+    }
+    Ok(())
+}
+// evidence is valid if a known validator signed two different headers at the same height
+pub fn verify_equivocation_evidence(
+    evidence: &EquivocationEvidence,
+    validators: &[VerifyingKey],
+) -> Result<()> {
+    let validator = deserialize_vk(&evidence.validator)?;
+    if !validators.contains(&validator) {
+        bail!("Evidence against unknown validator");
+    }
+    if [&evidence.first, &evidence.second]
+        .iter()
+        .any(|signed_header| {
+            (signed_header.header.height, signed_header.header.round)
+                != (evidence.height, evidence.round)
+        })
+    {
+        bail!(
+            "Evidence headers are not at height {} round {}",
+            evidence.height,
+            evidence.round
+        );
+    }
+    if evidence.first.header == evidence.second.header {
+        bail!("Evidence headers are identical");
+    }
+    for signed_header in [&evidence.first, &evidence.second] {
+        verify_signed_header(signed_header, &validator)?;
+    }
+    Ok(())
+}
+fn verify_signed_header(signed_header: &SignedHeader, validator: &VerifyingKey) -> Result<()> {
+    let signature = Signature::from_slice(&signed_header.signature)
+        .map_err(|_| anyhow!("Malformed Header Signature"))?;
+    validator
+        .verify(
+            &bincode::serialize(&signed_header.header).unwrap(),
+            &signature,
+        )
+        .map_err(|_| anyhow!("Invalid Header Signature"))
+}
+// a governance transaction must be approved by a quorum of the current validators
+// and may only take effect after the height it is included at
+pub fn verify_governance_transaction(
+    transaction: &GovernanceTransaction,
+    chain_id: &str,
+    validators: &[VerifyingKey],
+    quorum: u32,
+    height: u32,
+) -> Result<()> {
+    let proposal = &transaction.proposal;
+    if proposal.chain_id != chain_id {
+        bail!("Governance Proposal for chain {}", proposal.chain_id);
+    }
+    if proposal.activation_height <= height {
+        bail!(
+            "Governance Proposal activates at height {}, which is not after height {}",
+            proposal.activation_height,
+            height
+        );
+    }
+    match &proposal.change {
+        ValidatorSetChange::AddValidator(validator) => {
+            if validators.contains(&deserialize_vk(validator)?) {
+                bail!("Validator is already part of the set");
+            }
+        }
+        ValidatorSetChange::RemoveValidator(validator) => {
+            if !validators.contains(&deserialize_vk(validator)?) {
+                bail!("Validator is not part of the set");
+            }
+            if validators.len() == 1 {
+                bail!("The last validator can't be removed");
+            }
+        }
+        ValidatorSetChange::SetConsensusThreshold(Some(consensus_threshold)) => {
+            if *consensus_threshold == 0 || *consensus_threshold as usize > validators.len() {
+                bail!(
+                    "Consensus threshold must be between 1 and {}",
+                    validators.len()
+                );
+            }
+        }
+        ValidatorSetChange::SetConsensusThreshold(None) => {}
+    }
+    let signers = count_distinct_signers(
+        transaction
+            .approvals
+            .iter()
+            .map(|approval| (&approval.validator, &approval.signature)),
+        &proposal.to_bytes(),
+        validators,
+    )
+    .context("Invalid Governance Approval")?;
+    if signers < quorum {
+        bail!(
+            "Governance Proposal lacks a quorum: {} of {} approvals",
+            signers,
+            quorum
+        );
+    }
+    Ok(())
+}
+// a certificate finalizes the Block if a quorum of distinct validators signed its header
+pub fn verify_finality_certificate(
+    certificate: &FinalityCertificate,
+    block: &Block,
+    validators: &[VerifyingKey],
+    quorum: u32,
+) -> Result<()> {
+    if certificate.height != block.header.height || certificate.block_hash != block.hash() {
+        bail!("Finality Certificate is for a different Block");
+    }
+    let signers = count_distinct_signers(
+        certificate
+            .commitments
+            .iter()
+            .map(|commitment| (&commitment.validator, &commitment.signature)),
+        &block.to_bytes(),
+        validators,
+    )
+    .context("Invalid Block Commitment")?;
+    if signers < quorum {
+        bail!(
+            "Finality Certificate lacks a quorum: {} of {} commitments",
+            signers,
+            quorum
+        );
+    }
+    Ok(())
+}
+// the manifest must describe as many chunks as its leaves fill,
+// the certificate of its Block is verified separately
+pub fn verify_snapshot_manifest(manifest: &SnapshotManifest) -> Result<()> {
+    if manifest.chunk_size == 0 {
+        bail!("Snapshot chunk size must not be 0");
+    }
+    if manifest.leaf_count.div_ceil(manifest.chunk_size as u64) != manifest.chunk_count as u64 {
+        bail!(
+            "Snapshot of {} leaves can't have {} chunks",
+            manifest.leaf_count,
+            manifest.chunk_count
+        );
+    }
+    Ok(())
+}
+// every entry of the chunk must be the leaf at its index of the sequenced root
+// of the snapshot Block, and finalized at or below that Block
+pub fn verify_snapshot_chunk(chunk: &SnapshotChunk, manifest: &SnapshotManifest) -> Result<()> {
+    let header = &manifest.certified_block.block.header;
+    if chunk.height != header.height || chunk.index >= manifest.chunk_count {
+        bail!("Snapshot chunk is not part of the manifest");
+    }
+    let first_index = chunk.index as u64 * manifest.chunk_size as u64;
+    let expected_entries = (manifest.leaf_count - first_index).min(manifest.chunk_size as u64);
+    if chunk.entries.len() as u64 != expected_entries {
+        bail!(
+            "Snapshot chunk has {} entries, expected {}",
+            chunk.entries.len(),
+            expected_entries
+        );
+    }
+    for (offset, entry) in chunk.entries.iter().enumerate() {
+        if entry.proof.index != first_index + offset as u64
+            || entry.proof.leaf.hash != entry.finalized.transaction.hash()
+        {
+            bail!("Snapshot entry is out of place");
+        }
+        // the location is stored and served, so it must be the one the leaf commits to
+        if entry.proof.leaf.height != entry.finalized.height
+            || entry.proof.leaf.position != entry.finalized.position
+        {
+            bail!("Snapshot entry has a different location than its leaf");
+        }
+        if !verify_membership(&entry.proof, manifest.leaf_count, &header.sequenced_root) {
+            bail!("Snapshot entry is not part of the sequenced root");
+        }
+        if entry.finalized.height == 0 || entry.finalized.height > header.height {
+            bail!("Snapshot entry was finalized after the snapshot");
+        }
+    }
+    Ok(())
+}
+// signed transactions must be signed by their sender for this chain,
+// governance transactions carry their own approvals and don't need a sender
+pub fn verify_transaction_envelope(
+    transaction: &Transaction,
+    chain_id: &str,
+    require_signed_transactions: bool,
+) -> Result<()> {
+    let envelope = match &transaction.envelope {
+        Some(envelope) => envelope,
+        None if require_signed_transactions && transaction.governance.is_none() => {
+            bail!("Transaction is not signed")
+        }
+        None => return Ok(()),
+    };
+    if envelope.chain_id != chain_id {
+        bail!("Transaction was signed for chain {}", envelope.chain_id);
+    }
+    let sender = deserialize_vk(&envelope.sender)?;
+    let signature = Signature::from_slice(&envelope.signature)
+        .map_err(|_| anyhow!("Malformed Transaction Signature"))?;
+    sender
+        .verify(&transaction.signing_bytes(), &signature)
+        .map_err(|_| anyhow!("Invalid Transaction Signature"))
+}
+// the smallest number of validators such that any two quorums
+// overlap in at least one honest validator, 2f + 1 for n = 3f + 1
+pub fn bft_quorum(validator_count: usize) -> u32 {
+    (2 * validator_count / 3 + 1) as u32
+}
+pub fn get_committing_validator(round: u32, validators: Vec<VerifyingKey>) -> VerifyingKey {
     // returns the current validator
-    validators[round as usize % (validators.len() - 1) as usize]
+    validators[(round - 1) as usize % validators.len()]
 }
+// every validator can win the round
 fn choose_winner(random_commitment: BigInt, validators: Vec<VerifyingKey>) -> VerifyingKey {
-    let index = (random_commitment % (validators.len() - 1))
-        .to_u32()
+    let index = (random_commitment % validators.len())
+        .magnitude()
+        .to_usize()
         .unwrap();
-    validators[index as usize]
+    validators[index]
+}
+pub fn current_round(last_block_unix_timestamp: u32, round_duration: u32) -> u32 {
+    get_current_time().saturating_sub(last_block_unix_timestamp) / (round_duration) + 1
+}
+#[cfg(test)]
+fn signed_timeout_vote(keypair: &crate::crypto::ecdsa::Keypair, round: u32) -> TimeoutVote {
+    let mut vote = TimeoutVote {
+        validator: keypair.serialize_vk(),
+        chain_id: "test".to_string(),
+        height: 1,
+        round,
+        signature: vec![],
+    };
+    vote.signature = keypair.sign_data(&vote.to_bytes()).to_bytes().to_vec();
+    vote
+}
+#[test]
+fn test_verify_timeout_certificate() {
+    use crate::crypto::ecdsa::Keypair;
+    let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+    let validators: Vec<VerifyingKey> = keypairs.iter().map(|keypair| keypair.vk).collect();
+    let mut certificate = TimeoutCertificate {
+        chain_id: "test".to_string(),
+        height: 1,
+        round: 2,
+        votes: vec![
+            signed_timeout_vote(&keypairs[0], 2),
+            signed_timeout_vote(&keypairs[1], 2),
+        ],
+    };
+    assert!(verify_timeout_certificate(&certificate, &validators, 2).is_ok());
+    assert!(verify_timeout_certificate(&certificate, &validators, 3).is_err());
+    // all votes must be for the round of the certificate
+    certificate.votes[1] = signed_timeout_vote(&keypairs[1], 3);
+    assert!(verify_timeout_certificate(&certificate, &validators, 2).is_err());
+    // votes of another chain don't count
+    certificate.votes[1] = signed_timeout_vote(&keypairs[1], 2);
+    certificate.chain_id = "other".to_string();
+    assert!(verify_timeout_certificate(&certificate, &validators, 2).is_err());
+}
+#[test]
+fn test_count_distinct_signers() {
+    use crate::crypto::ecdsa::Keypair;
+    let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+    let validators: Vec<VerifyingKey> = keypairs[..2].iter().map(|keypair| keypair.vk).collect();
+    let sign = |keypair: &Keypair, message: &[u8]| {
+        (
+            keypair.serialize_vk(),
+            keypair.sign_data(message).to_bytes().to_vec(),
+        )
+    };
+    let count = |signatures: &[(GenericPublicKey, GenericSignature)]| {
+        count_distinct_signers(
+            signatures
+                .iter()
+                .map(|(validator, signature)| (validator, signature)),
+            b"message",
+            &validators,
+        )
+    };
+    // the same validator signing twice is counted once
+    let mut signatures = vec![
+        sign(&keypairs[0], b"message"),
+        sign(&keypairs[0], b"message"),
+    ];
+    assert_eq!(count(&signatures).unwrap(), 1);
+    signatures[1] = sign(&keypairs[1], b"message");
+    assert_eq!(count(&signatures).unwrap(), 2);
+    // signatures over another message or from outside the set are rejected
+    signatures[1] = sign(&keypairs[1], b"other");
+    assert!(count(&signatures).is_err());
+    signatures[1] = sign(&keypairs[2], b"message");
+    assert!(count(&signatures).is_err());
+}
+#[test]
+fn test_choose_winner() {
+    use crate::crypto::ecdsa::Keypair;
+    let validator = Keypair::new().vk;
+    assert_eq!(choose_winner(BigInt::from(7), vec![validator]), validator);
+    let validators: Vec<VerifyingKey> = (0..4).map(|_| Keypair::new().vk).collect();
+    // the last validator is reachable as well
+    for index in 0..validators.len() {
+        assert_eq!(
+            choose_winner(BigInt::from(index + 8), validators.clone()),
+            validators[index]
+        );
+    }
+}
+#[test]
+fn test_bft_quorum() {
+    assert_eq!(bft_quorum(1), 1);
+    assert_eq!(bft_quorum(3), 3);
+    assert_eq!(bft_quorum(4), 3);
+    assert_eq!(bft_quorum(5), 4);
+    assert_eq!(bft_quorum(7), 5);
+}
+#[cfg(test)]
+fn signed_header(
+    keypair: &crate::crypto::ecdsa::Keypair,
+    round: u32,
+    timestamp: u32,
+) -> SignedHeader {
+    let header = crate::types::BlockHeader {
+        height: 1,
+        round,
+        chain_id: "test".to_string(),
+        parent_hash: vec![0; 32],
+        transactions_root: vec![0; 32],
+        state_root: None,
+        sequenced_root: vec![0; 32],
+        proposer: keypair.serialize_vk(),
+        timestamp,
+    };
+    let signature = keypair
+        .sign_data(&bincode::serialize(&header).unwrap())
+        .to_bytes()
+        .to_vec();
+    SignedHeader { header, signature }
+}
+#[test]
+fn test_verify_equivocation_evidence() {
+    use crate::crypto::ecdsa::Keypair;
+    let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+    let validators: Vec<VerifyingKey> = keypairs.iter().map(|keypair| keypair.vk).collect();
+    let mut evidence = EquivocationEvidence {
+        validator: keypairs[0].serialize_vk(),
+        height: 1,
+        round: 1,
+        first: signed_header(&keypairs[0], 1, 1),
+        second: signed_header(&keypairs[0], 1, 1),
+    };
+    // signing the same header twice is not an equivocation
+    assert!(verify_equivocation_evidence(&evidence, &validators).is_err());
+    evidence.second = signed_header(&keypairs[1], 1, 2);
+    assert!(verify_equivocation_evidence(&evidence, &validators).is_err());
+    // neither is signing a re-proposal in a later round
+    evidence.second = signed_header(&keypairs[0], 2, 2);
+    assert!(verify_equivocation_evidence(&evidence, &validators).is_err());
+    evidence.second = signed_header(&keypairs[0], 1, 2);
+    assert!(verify_equivocation_evidence(&evidence, &validators).is_ok());
+    assert!(verify_equivocation_evidence(&evidence, &validators[1..]).is_err());
+}
+#[test]
+fn test_verify_governance_transaction() {
+    use crate::crypto::ecdsa::Keypair;
+    use crate::types::{GovernanceApproval, GovernanceProposal};
+    let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+    let validators: Vec<VerifyingKey> = keypairs[..3].iter().map(|keypair| keypair.vk).collect();
+    let proposal = GovernanceProposal {
+        chain_id: "test".to_string(),
+        change: ValidatorSetChange::AddValidator(keypairs[3].serialize_vk()),
+        activation_height: 10,
+    };
+    let approve = |keypair: &Keypair| GovernanceApproval {
+        validator: keypair.serialize_vk(),
+        signature: keypair.sign_data(&proposal.to_bytes()).to_bytes().to_vec(),
+    };
+    let transaction = GovernanceTransaction {
+        proposal: proposal.clone(),
+        approvals: vec![approve(&keypairs[0]), approve(&keypairs[1])],
+    };
+    assert!(verify_governance_transaction(&transaction, "test", &validators, 2, 5).is_ok());
+    assert!(verify_governance_transaction(&transaction, "test", &validators, 3, 5).is_err());
+    assert!(verify_governance_transaction(&transaction, "other", &validators, 2, 5).is_err());
+    // changes must activate after the current height
+    assert!(verify_governance_transaction(&transaction, "test", &validators, 2, 10).is_err());
+    // malformed keys are rejected at admission
+    let mut malformed = transaction.clone();
+    malformed.proposal.change = ValidatorSetChange::AddValidator(vec![0; 33]);
+    assert!(verify_governance_transaction(&malformed, "test", &validators, 2, 5).is_err());
+}
+#[test]
+fn test_verify_transaction_envelope() {
+    use crate::crypto::ecdsa::Keypair;
+    use crate::types::TransactionEnvelope;
+    let keypair = Keypair::new();
+    let mut transaction = Transaction {
+        data: vec![1, 2, 3],
+        timestamp: 0,
+        governance: None,
+        envelope: None,
+        priority_fee: 0,
+    };
+    // raw transactions are only accepted by trusted deployments
+    assert!(verify_transaction_envelope(&transaction, "test", false).is_ok());
+    assert!(verify_transaction_envelope(&transaction, "test", true).is_err());
+    transaction.envelope = Some(TransactionEnvelope {
+        sender: keypair.serialize_vk(),
+        nonce: 0,
+        chain_id: "test".to_string(),
+        signature: vec![],
+    });
+    let signature = keypair.sign_data(&transaction.signing_bytes());
+    transaction.envelope.as_mut().unwrap().signature = signature.to_bytes().to_vec();
+    assert!(verify_transaction_envelope(&transaction, "test", true).is_ok());
+    assert!(verify_transaction_envelope(&transaction, "other", true).is_err());
+    // the nonce is covered by the signature
+    transaction.envelope.as_mut().unwrap().nonce = 1;
+    assert!(verify_transaction_envelope(&transaction, "test", true).is_err());
+}
+#[test]
+fn test_verify_finality_certificate() {
+    use crate::crypto::ecdsa::Keypair;
+    use crate::types::BlockCommitment;
+    let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+    let validators: Vec<VerifyingKey> = keypairs.iter().map(|keypair| keypair.vk).collect();
+    let block = Block {
+        header: signed_header(&keypairs[0], 1, 0).header,
+        transactions: vec![],
+        signature: None,
+        commitments: None,
+    };
+    let commit = |keypair: &Keypair| BlockCommitment {
+        signature: keypair.sign_data(&block.to_bytes()).to_bytes().to_vec(),
+        validator: keypair.serialize_vk(),
+        timestamp: 0,
+    };
+    let certificate = FinalityCertificate {
+        height: block.header.height,
+        block_hash: block.hash(),
+        commitments: vec![commit(&keypairs[0]), commit(&keypairs[1])],
+    };
+    assert!(verify_finality_certificate(&certificate, &block, &validators, 2).is_ok());
+    assert!(verify_finality_certificate(&certificate, &block, &validators, 3).is_err());
+    // the certificate must be for this Block
+    let mut other_block = block.clone();
+    other_block.header.timestamp = 1;
+    assert!(verify_finality_certificate(&certificate, &other_block, &validators, 2).is_err());
 }
-pub fn current_round(last_block_unix_timestamp: u32) -> u32 {
-    (get_current_time() - last_block_unix_timestamp) / (ROUND_DURATION) + 1
+#[test]
+fn test_verify_snapshot_chunk() {
+    use crate::crypto::ecdsa::Keypair;
+    use crate::exclusion::{membership_proofs, sequenced_root, SequencedLeaf};
+    use crate::types::{CertifiedBlock, FinalizedTransaction, SnapshotEntry};
+    let finalized: Vec<FinalizedTransaction> = (0..5u8)
+        .map(|i| FinalizedTransaction {
+            height: 1,
+            position: i as u32,
+            transaction: Transaction {
+                data: vec![i],
+                timestamp: 0,
+                governance: None,
+                envelope: None,
+                priority_fee: 0,
+            },
+        })
+        .collect();
+    let mut sorted = finalized.clone();
+    sorted.sort_by_key(|finalized| finalized.transaction.hash());
+    let leaves: Vec<SequencedLeaf> = sorted
+        .iter()
+        .map(|finalized| SequencedLeaf {
+            hash: finalized.transaction.hash(),
+            height: finalized.height,
+            position: finalized.position,
+        })
+        .collect();
+    let mut header = signed_header(&Keypair::new(), 1, 0).header;
+    header.sequenced_root = sequenced_root(&leaves);
+    let manifest = SnapshotManifest {
+        certified_block: CertifiedBlock {
+            block: Block {
+                header,
+                transactions: vec![],
+                signature: None,
+                commitments: None,
+            },
+            certificate: FinalityCertificate {
+                height: 1,
+                block_hash: vec![],
+                commitments: vec![],
+            },
+        },
+        leaf_count: 5,
+        chunk_size: 2,
+        chunk_count: 3,
+    };
+    assert!(verify_snapshot_manifest(&manifest).is_ok());
+    let entries: Vec<SnapshotEntry> = sorted
+        .into_iter()
+        .zip(membership_proofs(&leaves))
+        .map(|(finalized, proof)| SnapshotEntry { finalized, proof })
+        .collect();
+    let chunk = |index: u32, entries: &[SnapshotEntry]| SnapshotChunk {
+        height: 1,
+        index,
+        entries: entries.to_vec(),
+    };
+    assert!(verify_snapshot_chunk(&chunk(0, &entries[..2]), &manifest).is_ok());
+    assert!(verify_snapshot_chunk(&chunk(2, &entries[4..]), &manifest).is_ok());
+    // entries must be at the indices of their chunk
+    assert!(verify_snapshot_chunk(&chunk(1, &entries[..2]), &manifest).is_err());
+    assert!(verify_snapshot_chunk(&chunk(0, &entries[..1]), &manifest).is_err());
+    // a Transaction that is not part of the sequenced root is rejected
+    let mut forged = entries[..2].to_vec();
+    forged[0].finalized.transaction.data = vec![9];
+    assert!(verify_snapshot_chunk(&chunk(0, &forged), &manifest).is_err());
+    // a location that the sequenced root doesn't commit to is rejected
+    let mut moved = entries[..2].to_vec();
+    moved[0].finalized.position += 1;
+    assert!(verify_snapshot_chunk(&chunk(0, &moved), &manifest).is_err());
+    let mut oversized = manifest.clone();
+    oversized.chunk_count = 4;
+    assert!(verify_snapshot_manifest(&oversized).is_err());
 }
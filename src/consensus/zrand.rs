@@ -1,11 +1,21 @@
+use anyhow::{anyhow, Result};
 use prover::ZK_RAND_ID;
 use risc0_zkvm::Receipt;
 use zk_logic::random_bytes_to_int;
 use zk_logic::types::CircuitOutputs;
+// checks the receipt against the zk-rand image and decodes its journal
+pub fn verify_receipt(receipt: &Receipt) -> Result<CircuitOutputs> {
+    receipt
+        .verify(ZK_RAND_ID)
+        .map_err(|e| anyhow!("Invalid Receipt: {}", e))?;
+    receipt
+        .journal
+        .decode()
+        .map_err(|e| anyhow!("Malformed Journal: {}", e))
+}
 #[allow(unused)]
 pub fn verify_random_number(receipt: Receipt) -> u32 {
-    receipt.verify(ZK_RAND_ID).expect("Invalid Random Number");
-    let outputs: CircuitOutputs = receipt.journal.decode().unwrap();
+    let outputs: CircuitOutputs = verify_receipt(&receipt).expect("Invalid Random Number");
     *random_bytes_to_int(&outputs.random_bytes)
         .to_u32_digits()
         .1
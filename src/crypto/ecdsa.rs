@@ -1,3 +1,4 @@
+use anyhow::{anyhow, Result};
 use k256::ecdsa::{signature::Signer, Signature, SigningKey, VerifyingKey};
 use rand_core::OsRng;
 
@@ -30,18 +31,19 @@ impl Default for Keypair {
         Self::new()
     }
 }
-pub fn deserialize_sk(sk_serialized: &[u8]) -> SigningKey {
-    SigningKey::from_bytes(sk_serialized.into()).unwrap()
+pub fn deserialize_sk(sk_serialized: &[u8]) -> Result<SigningKey> {
+    SigningKey::from_slice(sk_serialized)
+        .map_err(|_| anyhow!("Malformed secret key, expected 32 bytes"))
 }
-pub fn deserialize_vk(vk_serialized: &[u8]) -> VerifyingKey {
-    VerifyingKey::from_sec1_bytes(vk_serialized).unwrap()
+pub fn deserialize_vk(vk_serialized: &[u8]) -> Result<VerifyingKey> {
+    VerifyingKey::from_sec1_bytes(vk_serialized).map_err(|_| anyhow!("Malformed SEC1 public key"))
 }
 #[test]
 fn test_serialize_and_deserialize_sk() {
     let keypair = Keypair::new();
     let sk_before = keypair.sk.clone();
     let sk_serialized = keypair.serialize_sk();
-    let sk_deserialized = deserialize_sk(&sk_serialized);
+    let sk_deserialized = deserialize_sk(&sk_serialized).unwrap();
     assert_eq!(sk_before, sk_deserialized);
 }
 #[test]
@@ -49,7 +51,7 @@ fn test_serialize_and_deserialize_vk() {
     let keypair = Keypair::new();
     let vk_before = keypair.vk;
     let vk_serialized = keypair.serialize_vk();
-    let vk_deserialized = deserialize_vk(&vk_serialized);
+    let vk_deserialized = deserialize_vk(&vk_serialized).unwrap();
     assert_eq!(vk_before, vk_deserialized);
 }
 #[test]
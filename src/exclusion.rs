@@ -0,0 +1,386 @@
+use crate::types::GenericHash;
+use k256::sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// a crit-bit merkle tree over the hashes of all sequenced transactions. every branch splits
+// its leaves at the first bit in which they differ, so the shape only depends on the set of
+// hashes and an insert only rebuilds the path to the new leaf. the leaves are sorted
+// from left to right, following the bits of a hash ends at the leaf that shares the
+// longest prefix with it, which proves that the hash was never sequenced if they differ.
+// the root commits to the number of leaves
+#[derive(Debug, Clone, Default)]
+pub struct SequencedTree {
+    root: Option<Arc<TreeNode>>,
+}
+// every leaf commits to where its transaction was finalized
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SequencedLeaf {
+    pub hash: GenericHash,
+    pub height: u32,
+    pub position: u32,
+}
+#[derive(Debug)]
+enum TreeNode {
+    Leaf {
+        leaf: SequencedLeaf,
+        hash: GenericHash,
+    },
+    Branch {
+        bit: u32,
+        size: u64,
+        left: Arc<TreeNode>,
+        right: Arc<TreeNode>,
+        hash: GenericHash,
+    },
+}
+impl TreeNode {
+    fn leaf(leaf: SequencedLeaf) -> Arc<Self> {
+        Arc::new(Self::Leaf {
+            hash: hash_leaf(&leaf),
+            leaf,
+        })
+    }
+    fn branch(bit: u32, left: Arc<Self>, right: Arc<Self>) -> Arc<Self> {
+        let size = left.size() + right.size();
+        Arc::new(Self::Branch {
+            bit,
+            size,
+            hash: hash_node(bit, size, left.hash(), right.hash()),
+            left,
+            right,
+        })
+    }
+    fn hash(&self) -> &GenericHash {
+        match self {
+            Self::Leaf { hash, .. } | Self::Branch { hash, .. } => hash,
+        }
+    }
+    fn size(&self) -> u64 {
+        match self {
+            Self::Leaf { .. } => 1,
+            Self::Branch { size, .. } => *size,
+        }
+    }
+}
+impl SequencedTree {
+    pub fn from_leaves(leaves: &[SequencedLeaf]) -> Self {
+        Self::default().with(leaves)
+    }
+    pub fn root(&self) -> GenericHash {
+        match &self.root {
+            Some(root) => commitment(root.size(), root.hash()),
+            None => commitment(0, &Sha256::digest([])),
+        }
+    }
+    pub fn insert(&mut self, leaf: SequencedLeaf) {
+        let root = match &self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(TreeNode::leaf(leaf));
+                return;
+            }
+        };
+        let (_, closest, _) = walk(root, &leaf.hash);
+        // the hash is already part of the tree and keeps its first location
+        let critical_bit = match critical_bit(&closest.hash, &leaf.hash) {
+            Some(critical_bit) => critical_bit,
+            None => return,
+        };
+        self.root = Some(insert_at(root, leaf, critical_bit));
+    }
+    // the tree after inserting the leaves, the nodes of this tree are shared and not modified
+    pub fn with(&self, leaves: &[SequencedLeaf]) -> Self {
+        let mut tree = self.clone();
+        for leaf in leaves {
+            tree.insert(leaf.clone());
+        }
+        tree
+    }
+    // returns None if the hash is part of the tree
+    pub fn exclusion_proof(&self, hash: &[u8]) -> Option<ExclusionProof> {
+        let root = match &self.root {
+            Some(root) => root,
+            None => {
+                return Some(ExclusionProof {
+                    hash: hash.to_vec(),
+                    leaf: None,
+                    path: Vec::new(),
+                })
+            }
+        };
+        let (path, leaf, _) = walk(root, hash);
+        (leaf.hash != hash).then(|| ExclusionProof {
+            hash: hash.to_vec(),
+            leaf: Some(leaf),
+            path,
+        })
+    }
+    // returns None if the hash is not part of the tree
+    pub fn membership_proof(&self, hash: &[u8]) -> Option<MembershipProof> {
+        let (path, leaf, index) = walk(self.root.as_ref()?, hash);
+        (leaf.hash == hash).then_some(MembershipProof { index, leaf, path })
+    }
+}
+// a branch on the way to a leaf, with the subtree that was not taken
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PathStep {
+    pub bit: u32,
+    pub sibling: GenericHash,
+    pub sibling_size: u64,
+}
+// the leaf that `hash` leads to and the branches from the root down to it,
+// there is no leaf if nothing was sequenced yet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExclusionProof {
+    pub hash: GenericHash,
+    pub leaf: Option<SequencedLeaf>,
+    pub path: Vec<PathStep>,
+}
+// proves that `leaf` is the leaf at `index` from the left
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MembershipProof {
+    pub index: u64,
+    pub leaf: SequencedLeaf,
+    pub path: Vec<PathStep>,
+}
+pub fn sequenced_root(leaves: &[SequencedLeaf]) -> GenericHash {
+    SequencedTree::from_leaves(leaves).root()
+}
+// proves that every leaf is part of the set, in the order of `sorted_leaves`
+pub fn membership_proofs(sorted_leaves: &[SequencedLeaf]) -> Vec<MembershipProof> {
+    let tree = SequencedTree::from_leaves(sorted_leaves);
+    sorted_leaves
+        .iter()
+        .filter_map(|leaf| tree.membership_proof(&leaf.hash))
+        .collect()
+}
+pub fn verify_membership(proof: &MembershipProof, leaf_count: u64, root: &[u8]) -> bool {
+    match root_from_path(&proof.leaf.hash, &proof.leaf, &proof.path) {
+        Some((node, size, index)) => {
+            size == leaf_count && index == proof.index && commitment(size, &node) == root
+        }
+        None => false,
+    }
+}
+pub fn verify_exclusion_proof(proof: &ExclusionProof, root: &[u8]) -> bool {
+    match &proof.leaf {
+        Some(leaf) if leaf.hash != proof.hash => {
+            match root_from_path(&proof.hash, leaf, &proof.path) {
+                Some((node, size, _)) => commitment(size, &node) == root,
+                None => false,
+            }
+        }
+        Some(_) => false,
+        None => proof.path.is_empty() && commitment(0, &Sha256::digest([])) == root,
+    }
+}
+// follows the bits of the key from the root down to a leaf,
+// returns the branches on the way, the leaf and its index
+fn walk(root: &Arc<TreeNode>, key: &[u8]) -> (Vec<PathStep>, SequencedLeaf, u64) {
+    let mut path: Vec<PathStep> = Vec::new();
+    let mut index = 0;
+    let mut node = root;
+    loop {
+        match node.as_ref() {
+            TreeNode::Leaf { leaf, .. } => return (path, leaf.clone(), index),
+            TreeNode::Branch {
+                bit, left, right, ..
+            } => {
+                let (next, sibling) = if bit_at(key, *bit) {
+                    index += left.size();
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                path.push(PathStep {
+                    bit: *bit,
+                    sibling: sibling.hash().clone(),
+                    sibling_size: sibling.size(),
+                });
+                node = next;
+            }
+        }
+    }
+}
+// the new leaf is placed above the first branch that splits at a later bit
+fn insert_at(node: &Arc<TreeNode>, leaf: SequencedLeaf, critical_bit: u32) -> Arc<TreeNode> {
+    match node.as_ref() {
+        TreeNode::Branch {
+            bit, left, right, ..
+        } if *bit < critical_bit => {
+            if bit_at(&leaf.hash, *bit) {
+                TreeNode::branch(*bit, left.clone(), insert_at(right, leaf, critical_bit))
+            } else {
+                TreeNode::branch(*bit, insert_at(left, leaf, critical_bit), right.clone())
+            }
+        }
+        _ => {
+            if bit_at(&leaf.hash, critical_bit) {
+                TreeNode::branch(critical_bit, node.clone(), TreeNode::leaf(leaf))
+            } else {
+                TreeNode::branch(critical_bit, TreeNode::leaf(leaf), node.clone())
+            }
+        }
+    }
+}
+// recomputes the subtree above a leaf, the directions follow the bits of `key`.
+// returns its hash, the number of leaves and the leaf's index
+fn root_from_path(
+    key: &[u8],
+    leaf: &SequencedLeaf,
+    path: &[PathStep],
+) -> Option<(GenericHash, u64, u64)> {
+    // branches split at increasing bits from the root down
+    if path.windows(2).any(|steps| steps[0].bit >= steps[1].bit) {
+        return None;
+    }
+    let mut node = hash_leaf(leaf);
+    let (mut size, mut index) = (1u64, 0u64);
+    for step in path.iter().rev() {
+        let go_right = bit_at(key, step.bit);
+        // the leaf must lie on the path of the key
+        if go_right != bit_at(&leaf.hash, step.bit) || step.sibling_size == 0 {
+            return None;
+        }
+        size = size.checked_add(step.sibling_size)?;
+        node = if go_right {
+            index += step.sibling_size;
+            hash_node(step.bit, size, &step.sibling, &node)
+        } else {
+            hash_node(step.bit, size, &node, &step.sibling)
+        };
+    }
+    Some((node, size, index))
+}
+// bits are numbered from the most significant bit of the first byte,
+// missing bytes of shorter keys count as zeros
+fn bit_at(key: &[u8], bit: u32) -> bool {
+    let byte = key.get(bit as usize / 8).copied().unwrap_or(0);
+    (byte >> (7 - bit % 8)) & 1 == 1
+}
+fn critical_bit(a: &[u8], b: &[u8]) -> Option<u32> {
+    (0..a.len().max(b.len()) as u32 * 8).find(|bit| bit_at(a, *bit) != bit_at(b, *bit))
+}
+fn commitment(leaf_count: u64, node: &[u8]) -> GenericHash {
+    let mut hasher = Sha256::new();
+    hasher.update(leaf_count.to_le_bytes());
+    hasher.update(node);
+    hasher.finalize().to_vec()
+}
+// leaves and branches are hashed with different prefixes
+fn hash_leaf(leaf: &SequencedLeaf) -> GenericHash {
+    Sha256::new()
+        .chain_update([0u8])
+        .chain_update((leaf.hash.len() as u32).to_le_bytes())
+        .chain_update(&leaf.hash)
+        .chain_update(leaf.height.to_le_bytes())
+        .chain_update(leaf.position.to_le_bytes())
+        .finalize()
+        .to_vec()
+}
+fn hash_node(bit: u32, size: u64, left: &[u8], right: &[u8]) -> GenericHash {
+    Sha256::new()
+        .chain_update([1u8])
+        .chain_update(bit.to_le_bytes())
+        .chain_update(size.to_le_bytes())
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .to_vec()
+}
+
+#[cfg(test)]
+fn test_leaves(hashes: impl Iterator<Item = GenericHash>) -> Vec<SequencedLeaf> {
+    hashes
+        .enumerate()
+        .map(|(position, hash)| SequencedLeaf {
+            hash,
+            height: 1,
+            position: position as u32,
+        })
+        .collect()
+}
+#[test]
+fn test_exclusion_proofs() {
+    for size in 0..12u8 {
+        // even hashes are part of the set, odd ones are not
+        let leaves = test_leaves((0..size).map(|i| vec![i * 2 + 2]));
+        let tree = SequencedTree::from_leaves(&leaves);
+        let root = tree.root();
+        for candidate in 0..(size * 2 + 4) {
+            let hash = vec![candidate];
+            let is_sequenced = leaves.iter().any(|leaf| leaf.hash == hash);
+            match tree.exclusion_proof(&hash) {
+                Some(proof) => {
+                    assert!(!is_sequenced);
+                    assert!(verify_exclusion_proof(&proof, &root));
+                }
+                None => assert!(is_sequenced),
+            }
+        }
+    }
+}
+#[test]
+fn test_invalid_exclusion_proofs() {
+    let tree = SequencedTree::from_leaves(&test_leaves((0..5u8).map(|i| vec![i * 2 + 2])));
+    let root = tree.root();
+    let proof = tree.exclusion_proof(&[5]).unwrap();
+    assert!(verify_exclusion_proof(&proof, &root));
+    // a hash that is part of the set can't be excluded with its own leaf
+    let mut included = proof.clone();
+    included.hash = proof.leaf.clone().unwrap().hash;
+    assert!(!verify_exclusion_proof(&included, &root));
+    // the leaf must lie on the path of the hash
+    let mut other = tree.exclusion_proof(&[7]).unwrap();
+    other.hash = vec![3];
+    assert!(!verify_exclusion_proof(&other, &root));
+    // hiding a branch is detected
+    let mut hidden = proof.clone();
+    hidden.path.remove(0);
+    assert!(!verify_exclusion_proof(&hidden, &root));
+    assert!(!verify_exclusion_proof(&proof, &sequenced_root(&[])));
+}
+#[test]
+fn test_membership_proofs() {
+    for size in 1..12u8 {
+        let leaves = test_leaves((0..size).map(|i| vec![i * 2 + 2]));
+        let root = sequenced_root(&leaves);
+        let proofs = membership_proofs(&leaves);
+        assert_eq!(proofs.len(), leaves.len());
+        for (index, proof) in proofs.iter().enumerate() {
+            assert_eq!(proof.leaf, leaves[index]);
+            assert_eq!(proof.index, index as u64);
+            assert!(verify_membership(proof, size as u64, &root));
+            assert!(!verify_membership(proof, size as u64 + 1, &root));
+        }
+        // a proof only holds for the hash and the location it was created for
+        let mut forged = proofs[0].clone();
+        forged.leaf.hash = vec![1];
+        assert!(!verify_membership(&forged, size as u64, &root));
+        let mut moved = proofs[0].clone();
+        moved.leaf.position += 1;
+        assert!(!verify_membership(&moved, size as u64, &root));
+        let mut later = proofs[0].clone();
+        later.leaf.height += 1;
+        assert!(!verify_membership(&later, size as u64, &root));
+    }
+}
+#[test]
+fn test_sequenced_tree_inserts() {
+    let leaves = test_leaves((0..32u8).map(|i| vec![i.wrapping_mul(37), i]));
+    let mut reversed = leaves.clone();
+    reversed.reverse();
+    // the root only depends on the set of leaves
+    let tree = SequencedTree::from_leaves(&leaves);
+    assert_eq!(tree.root(), SequencedTree::from_leaves(&reversed).root());
+    let base = SequencedTree::from_leaves(&leaves[..16]);
+    let extended = base.with(&leaves[16..]);
+    assert_eq!(extended.root(), tree.root());
+    // the base tree is not modified by a candidate
+    assert_eq!(base.root(), sequenced_root(&leaves[..16]));
+    // a hash keeps the location it was first sequenced at
+    let mut repeated = leaves[..4].to_vec();
+    repeated[0].height = 2;
+    assert_eq!(extended.with(&repeated).root(), tree.root());
+}
@@ -1,18 +1,39 @@
-use crate::types::ConsensusCommitment;
-use crate::{consensus::logic::current_round, types::Block};
+use crate::types::{ConsensusCommitment, EquivocationEvidence, TimeoutCertificate, TimeoutVote};
+use crate::{consensus::logic::current_round, get_current_time, types::Block};
 use colored::Colorize;
+use k256::ecdsa::VerifyingKey;
 use reqwest::{Client, Response};
-use std::{env, time::Duration};
+use std::collections::HashMap;
+use std::time::Duration;
 use tokio::time::sleep;
-pub type Peer = &'static str;
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peer {
+    pub address: String,
+    pub validator: VerifyingKey,
+}
+// every request between peers carries the hex encoded genesis hash,
+// requests from nodes of a different network are rejected
+pub const GENESIS_HASH_HEADER: &str = "genesis-hash";
+// seconds a peer of a different network is skipped before it is handshaked again
+pub const INCOMPATIBLE_PEER_BACKOFF: u32 = 300;
 pub struct Gossipper {
     pub peers: Vec<Peer>,
+    // peers that failed the handshake and the unix time of their next handshake
+    pub incompatible: HashMap<String, u32>,
     pub client: Client,
+    pub local_validator: VerifyingKey,
+    pub genesis_hash: String,
 }
-async fn send_proposal(client: Client, peer: Peer, json_block: String) -> Option<Response> {
+async fn send_proposal(
+    client: Client,
+    peer: String,
+    genesis_hash: String,
+    json_block: String,
+) -> Option<Response> {
     let response: Option<Response> = match client
         .post(format!("http://{}{}", &peer, "/propose"))
         .header("Content-Type", "application/json")
+        .header(GENESIS_HASH_HEADER, genesis_hash)
         .body(json_block)
         .timeout(Duration::from_secs(3))
         .send()
@@ -24,30 +45,87 @@ async fn send_proposal(client: Client, peer: Peer, json_block: String) -> Option
     response
 }
 impl Gossipper {
-    pub async fn gossip_pending_block(&self, block: Block, last_block_unix_timestamp: u32) {
-        for peer in self.peers.clone() {
+    pub fn new(peers: Vec<Peer>, local_validator: VerifyingKey, genesis_hash: String) -> Self {
+        Self {
+            peers,
+            incompatible: HashMap::new(),
+            client: Client::new(),
+            local_validator,
+            genesis_hash,
+        }
+    }
+    // all peers except the one that runs under the local validator key
+    // and those that are backed off after a failed handshake
+    pub fn remote_peers(&self) -> Vec<Peer> {
+        let unix_timestamp = get_current_time();
+        self.peers
+            .iter()
+            .filter(|peer| peer.validator != self.local_validator)
+            .filter(|peer| {
+                self.incompatible
+                    .get(&peer.address)
+                    .map_or(true, |retry_at| *retry_at <= unix_timestamp)
+            })
+            .cloned()
+            .collect()
+    }
+    // adds a peer or replaces the peer at the same address,
+    // a changed peer is handshaked again right away
+    pub fn add_peer(&mut self, peer: Peer) {
+        match self
+            .peers
+            .iter_mut()
+            .find(|known| known.address == peer.address)
+        {
+            Some(known) if *known == peer => {}
+            Some(known) => {
+                self.incompatible.remove(&peer.address);
+                *known = peer;
+            }
+            None => self.peers.push(peer),
+        }
+    }
+    // the peer is skipped until the backoff expired and is handshaked again afterwards
+    pub fn mark_incompatible(&mut self, address: &str) {
+        self.incompatible.insert(
+            address.to_string(),
+            get_current_time() + INCOMPATIBLE_PEER_BACKOFF,
+        );
+    }
+    pub fn mark_compatible(&mut self, address: &str) {
+        self.incompatible.remove(address);
+    }
+    pub async fn gossip_pending_block(
+        &self,
+        block: Block,
+        last_block_unix_timestamp: u32,
+        round_duration: u32,
+    ) {
+        for peer in self.remote_peers() {
             let client_clone = self.client.clone();
-            let peer_clone = peer;
+            let peer_clone = peer.address;
+            let genesis_hash_clone = self.genesis_hash.clone();
             let json_block: String = serde_json::to_string(&block).unwrap();
-            let this_node = env::var("API_HOST_WITH_PORT").unwrap_or("0.0.0.0:8080".to_string());
-            if docker_skip_self(&this_node, peer) {
-                continue;
-            };
             tokio::spawn(async move {
-                let start_round = current_round(last_block_unix_timestamp);
-                let round = current_round(last_block_unix_timestamp);
+                let start_round = current_round(last_block_unix_timestamp, round_duration);
+                let round = current_round(last_block_unix_timestamp, round_duration);
                 if start_round < round {
                     println!("[Warning] Gossipping old Block");
                 }
-                let response =
-                    match send_proposal(client_clone.clone(), peer_clone, json_block.clone()).await
-                    {
-                        Some(r) => r
-                            .text()
-                            .await
-                            .unwrap_or("[Err] Peer unresponsive".to_string()),
-                        None => "[Err] Failed to send request".to_string(),
-                    };
+                let response = match send_proposal(
+                    client_clone.clone(),
+                    peer_clone.clone(),
+                    genesis_hash_clone,
+                    json_block.clone(),
+                )
+                .await
+                {
+                    Some(r) => r
+                        .text()
+                        .await
+                        .unwrap_or("[Err] Peer unresponsive".to_string()),
+                    None => "[Err] Failed to send request".to_string(),
+                };
                 if response == "[Ok] Block was processed" {
                     println!(
                         "{}",
@@ -62,20 +140,60 @@ impl Gossipper {
             });
         }
     }
+    pub async fn gossip_timeout_vote(&self, vote: TimeoutVote) {
+        let json_vote: String = serde_json::to_string(&vote).unwrap();
+        self.broadcast("/timeout", json_vote);
+    }
+    pub async fn gossip_timeout_certificate(&self, certificate: TimeoutCertificate) {
+        let json_certificate: String = serde_json::to_string(&certificate).unwrap();
+        self.broadcast("/view_change", json_certificate);
+    }
+    pub async fn gossip_equivocation_evidence(&self, evidence: EquivocationEvidence) {
+        let json_evidence: String = serde_json::to_string(&evidence).unwrap();
+        self.broadcast("/evidence", json_evidence);
+    }
+    // fire and forget, unreachable peers are skipped
+    fn broadcast(&self, route: &'static str, json: String) {
+        for peer in self.remote_peers() {
+            let client_clone = self.client.clone();
+            let genesis_hash_clone = self.genesis_hash.clone();
+            let json_clone = json.clone();
+            tokio::spawn(async move {
+                if client_clone
+                    .post(format!("http://{}{}", &peer.address, route))
+                    .header("Content-Type", "application/json")
+                    .header(GENESIS_HASH_HEADER, genesis_hash_clone)
+                    .body(json_clone)
+                    .timeout(Duration::from_secs(10))
+                    .send()
+                    .await
+                    .is_err()
+                {
+                    println!(
+                        "{}",
+                        format_args!(
+                            "{} Failed to send {} to peer: {}",
+                            "[Warning]".yellow(),
+                            route,
+                            &peer.address
+                        )
+                    )
+                }
+            });
+        }
+    }
     pub async fn gossip_consensus_commitment(&self, commitment: ConsensusCommitment) {
         let json_commitment: String = serde_json::to_string(&commitment).unwrap();
-        for peer in self.peers.clone() {
+        for peer in self.remote_peers() {
             let client_clone = self.client.clone();
-            let peer_clone = peer;
+            let peer_clone = peer.address;
+            let genesis_hash_clone = self.genesis_hash.clone();
             let json_commitment_clone: String = json_commitment.clone();
-            let this_node = env::var("API_HOST_WITH_PORT").unwrap_or("0.0.0.0:8080".to_string());
-            if docker_skip_self(&this_node, peer) {
-                continue;
-            };
             tokio::spawn(async move {
                 match client_clone
                     .post(format!("http://{}{}", &peer_clone, "/commit"))
                     .header("Content-Type", "application/json")
+                    .header(GENESIS_HASH_HEADER, genesis_hash_clone)
                     .body(json_commitment_clone)
                     .timeout(Duration::from_secs(10))
                     .send()
@@ -96,15 +214,39 @@ impl Gossipper {
         }
     }
 }
-pub fn docker_skip_self(this_node: &str, peer: &str) -> bool {
-    if this_node == "0.0.0.0:8080" && peer == "rust-node-1:8080" {
-        return true;
-    } else if this_node == "0.0.0.0:8081" && peer == "rust-node-2:8081" {
-        return true;
-    } else if this_node == "0.0.0.0:8082" && peer == "rust-node-3:8082" {
-        return true;
-    } else if this_node == "0.0.0.0:8083" && peer == "rust-node-4:8083" {
-        return true;
-    }
-    false
+
+#[test]
+fn test_incompatible_peer_backoff() {
+    use crate::crypto::ecdsa::Keypair;
+    let local = Keypair::new();
+    let peer = Peer {
+        address: "rust-node-2:8081".to_string(),
+        validator: Keypair::new().vk,
+    };
+    let mut gossipper = Gossipper::new(
+        vec![
+            Peer {
+                address: "rust-node-1:8080".to_string(),
+                validator: local.vk,
+            },
+            peer.clone(),
+        ],
+        local.vk,
+        String::new(),
+    );
+    assert_eq!(gossipper.remote_peers(), vec![peer.clone()]);
+    gossipper.mark_incompatible(&peer.address);
+    assert!(gossipper.remote_peers().is_empty());
+    // the unchanged peer stays backed off, a changed peer is handshaked again
+    gossipper.add_peer(peer.clone());
+    assert!(gossipper.remote_peers().is_empty());
+    let changed = Peer {
+        address: peer.address.clone(),
+        validator: Keypair::new().vk,
+    };
+    gossipper.add_peer(changed.clone());
+    assert_eq!(gossipper.remote_peers(), vec![changed]);
+    gossipper.mark_incompatible(&peer.address);
+    gossipper.mark_compatible(&peer.address);
+    assert_eq!(gossipper.remote_peers().len(), 1);
 }
@@ -1,77 +1,584 @@
-use crate::config::consensus::CONSENSUS_THRESHOLD;
-#[cfg(not(feature = "sqlite"))]
-use crate::state::server::InMemoryBlockStore;
-#[cfg(feature = "sqlite")]
-use crate::state::server::SqLiteBlockStore;
-use crate::types::BlockCommitment;
-use crate::types::GenericSignature;
-use crate::{crypto::ecdsa::deserialize_vk, types::Block};
-use crate::{get_current_time, ServerState};
+use crate::exclusion::{SequencedLeaf, SequencedTree};
+use crate::state::server::{BlockStore, InMemoryConsensus};
+use crate::state::snapshot::{build_snapshot, replay_governance, snapshot_height};
+use crate::state::trie::{StagedTrie, TrieNodes};
+use crate::types::{
+    transactions_root, BlockCommitment, CertifiedBlock, EquivocationEvidence, FinalityCertificate,
+    FinalizedTransaction, LastSigned, SnapshotChunk, SnapshotManifest, Transaction,
+};
+use crate::types::{GenericHash, GenericPublicKey, GenericSignature};
+use crate::{
+    consensus::logic::{
+        verify_finality_certificate, verify_governance_transaction, verify_transaction_envelope,
+    },
+    crypto::ecdsa::deserialize_vk,
+    types::Block,
+};
+use crate::{get_current_time, Root, ServerState};
+use anyhow::{bail, Result};
 use colored::Colorize;
 use k256::ecdsa::signature::{SignerMut, Verifier};
-use k256::ecdsa::Signature;
+use k256::ecdsa::{Signature, VerifyingKey};
 use patricia_trie::{
     insert_leaf,
     store::types::{Hashable, Leaf, Node},
 };
-use reqwest::Response;
+use std::cmp::Reverse;
+use std::collections::{hash_map::Entry, HashMap};
 
-pub async fn handle_synchronization_response(
+fn transaction_leaf(transaction: &Transaction) -> Leaf {
+    let mut leaf = Leaf::new(transaction.key(), Some(transaction.data.clone()));
+    leaf.hash();
+    leaf
+}
+// inserts the transactions into the trie and returns the new root,
+// roots that were derived earlier remain valid.
+// a transaction is only inserted once, repeated transactions are skipped
+pub fn apply_transactions(
+    merkle_trie_state: &mut StagedTrie<'_>,
+    root: Root,
+    transactions: &[Transaction],
+) -> Root {
+    let mut root_node = Node::Root(root);
+    let mut inserted: Vec<GenericHash> = Vec::new();
+    for transaction in transactions {
+        let transaction_hash = transaction.hash();
+        if inserted.contains(&transaction_hash) {
+            continue;
+        }
+        inserted.push(transaction_hash);
+        let mut leaf = transaction_leaf(transaction);
+        let new_root = insert_leaf(merkle_trie_state, &mut leaf, root_node);
+        root_node = Node::Root(new_root);
+    }
+    root_node.unwrap_as_root()
+}
+// the leaves of the transactions finalized by a Block at this height
+pub fn sequenced_leaves(height: u32, transactions: &[Transaction]) -> Vec<SequencedLeaf> {
+    transactions
+        .iter()
+        .enumerate()
+        .map(|(position, transaction)| SequencedLeaf {
+            hash: transaction.hash(),
+            height,
+            position: position as u32,
+        })
+        .collect()
+}
+// the sequenced root after finalizing the transactions on top of the local tip
+pub fn next_sequenced_root(
+    sequenced_tree: &SequencedTree,
+    height: u32,
+    transactions: &[Transaction],
+) -> GenericHash {
+    sequenced_tree
+        .with(&sequenced_leaves(height, transactions))
+        .root()
+}
+// number of evicted transactions whose status is reported as dropped
+pub const DROPPED_TRANSACTIONS_LIMIT: usize = 10_000;
+// removes transactions that waited in the pool for longer than the ttl
+pub fn evict_expired_transactions(
     state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
-    response: Response,
-    next_height: u32,
-) {
-    println!("[Info] Querying Block: {}", &next_height);
-    let block_serialized = response.text().await.unwrap();
-    if block_serialized != "[Warning] Requested Block that does not exist" {
-        let block: Block = serde_json::from_str(&block_serialized).unwrap();
-        #[cfg(not(feature = "sqlite"))]
-        state_lock
-            .block_state
-            .insert_block(next_height, block.clone());
-        #[cfg(feature = "sqlite")]
-        state_lock
-            .block_state
-            .insert_block(next_height, block.clone());
-        // insert transactions into the trie
-        let mut root_node = Node::Root(state_lock.merkle_trie_root.clone());
-        let transactions = &block.transactions;
-        for transaction in transactions {
-            let mut leaf = Leaf::new(Vec::new(), Some(transaction.data.clone()));
-            leaf.hash();
-            leaf.key = leaf
-                .hash
-                .clone()
-                .unwrap()
-                .iter()
-                .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
-                .collect();
-            leaf.hash();
-            let new_root = insert_leaf(&mut state_lock.merkle_trie_state, &mut leaf, root_node);
-            root_node = Node::Root(new_root);
+) -> Result<()> {
+    let expired = state_lock
+        .mempool_limits
+        .expired(&state_lock.pool_state.get_entries()?, get_current_time());
+    if !expired.is_empty() {
+        state_lock.pool_state.remove_transactions(&expired)?;
+        for hash in &expired {
+            if state_lock.dropped_transactions.len() == DROPPED_TRANSACTIONS_LIMIT {
+                state_lock.dropped_transactions.pop_front();
+            }
+            state_lock.dropped_transactions.push_back(hash.clone());
         }
-        // update trie root
-        state_lock.merkle_trie_root = root_node.unwrap_as_root();
-        state_lock.consensus_state.reinitialize();
         println!(
             "{}",
-            format_args!("{} Synchronized Block: {}", "[Info]".green(), next_height)
+            format_args!(
+                "{} Evicted {} expired Transactions",
+                "[Info]".green(),
+                expired.len()
+            )
         );
+    }
+    Ok(())
+}
+// picks transactions by priority fee and then arrival until the Block is full,
+// a signed transaction is only picked once its nonce is next for its sender.
+// `transactions` must be in the order they arrived in
+pub fn select_transactions(
+    block_state: &dyn BlockStore,
+    mut transactions: Vec<Transaction>,
+    max_block_bytes: u64,
+) -> Result<Vec<Transaction>> {
+    transactions.sort_by_key(|transaction| Reverse(transaction.priority_fee));
+    let mut next_nonces: HashMap<GenericPublicKey, u64> = HashMap::new();
+    let mut selected: Vec<Transaction> = Vec::new();
+    let mut block_bytes: u64 = 0;
+    let mut remaining = transactions;
+    // a higher nonce becomes eligible once the lower nonces of its sender were picked
+    loop {
+        let selected_before = selected.len();
+        let mut deferred: Vec<Transaction> = Vec::new();
+        for transaction in remaining {
+            let size = transaction.size() as u64;
+            if block_bytes + size > max_block_bytes {
+                continue;
+            }
+            if let Some(envelope) = &transaction.envelope {
+                let next_nonce = match next_nonces.entry(envelope.sender.clone()) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        entry.insert(block_state.get_next_nonce(&envelope.sender)?)
+                    }
+                };
+                if envelope.nonce > *next_nonce {
+                    deferred.push(transaction);
+                    continue;
+                }
+                if envelope.nonce < *next_nonce {
+                    continue;
+                }
+                *next_nonce += 1;
+            }
+            block_bytes += size;
+            selected.push(transaction);
+        }
+        if selected.len() == selected_before || deferred.is_empty() {
+            break;
+        }
+        remaining = deferred;
+    }
+    Ok(selected)
+}
+// the signed transactions of each sender in a Block must continue at the sender's next nonce
+pub fn verify_transaction_nonces(
+    block_state: &dyn BlockStore,
+    transactions: &[Transaction],
+) -> Result<bool> {
+    let mut next_nonces: HashMap<GenericPublicKey, u64> = HashMap::new();
+    for envelope in transactions
+        .iter()
+        .filter_map(|transaction| transaction.envelope.as_ref())
+    {
+        let next_nonce = match next_nonces.entry(envelope.sender.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(block_state.get_next_nonce(&envelope.sender)?),
+        };
+        if envelope.nonce != *next_nonce {
+            return Ok(false);
+        }
+        *next_nonce += 1;
+    }
+    Ok(true)
+}
+// a proposal must extend our tip and its header must match its contents
+fn verify_proposal_header(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    proposal: &Block,
+) -> Result<bool> {
+    let round_winner = state_lock
+        .consensus_state
+        .round_winner
+        .map(|round_winner| round_winner.to_sec1_bytes().to_vec());
+    let last_block_unix_timestamp = state_lock.block_state.latest_block()?.header.timestamp;
+    let rejection = if round_winner != Some(proposal.header.proposer.clone()) {
+        Some("Proposer is not the round winner")
+    } else if proposal.header.round != state_lock.consensus_state.round(last_block_unix_timestamp) {
+        Some("Proposal is not for the current round")
+    } else {
+        block_rejection(state_lock, proposal)?
+    };
+    match rejection {
+        Some(reason) => {
+            println!(
+                "{}",
+                format_args!("{} Invalid Proposal: {}", "[Warning]".yellow(), reason)
+            );
+            Ok(false)
+        }
+        None => Ok(true),
+    }
+}
+// checks that a Block extends our tip and that its header matches its contents,
+// returns the reason if it doesn't
+fn block_rejection(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    proposal: &Block,
+) -> Result<Option<&'static str>> {
+    let previous_block = state_lock.block_state.latest_block()?;
+    let header = &proposal.header;
+    let governance_is_valid = proposal.transactions.iter().all(|transaction| {
+        transaction.governance.as_ref().map_or(true, |governance| {
+            verify_governance_transaction(
+                governance,
+                &state_lock.genesis.chain_id,
+                &state_lock.consensus_state.validators,
+                state_lock.consensus_state.quorum(),
+                header.height,
+            )
+            .is_ok()
+        })
+    });
+    let envelopes_are_valid = proposal.transactions.iter().all(|transaction| {
+        verify_transaction_envelope(
+            transaction,
+            &state_lock.genesis.chain_id,
+            state_lock.genesis.require_signed_transactions,
+        )
+        .is_ok()
+    });
+    let block_bytes: u64 = proposal
+        .transactions
+        .iter()
+        .map(|transaction| transaction.size() as u64)
+        .sum();
+    let mut transaction_hashes: Vec<GenericHash> = Vec::new();
+    let mut has_duplicates = false;
+    for transaction in &proposal.transactions {
+        let transaction_hash = transaction.hash();
+        if transaction_hashes.contains(&transaction_hash)
+            || state_lock
+                .block_state
+                .contains_transaction(&transaction_hash)?
+        {
+            has_duplicates = true;
+        }
+        transaction_hashes.push(transaction_hash);
+    }
+    let rejection: Option<&str> = if header.chain_id != state_lock.genesis.chain_id {
+        Some("Chain id mismatch")
+    } else if header.height != previous_block.header.height + 1
+        || header.parent_hash != previous_block.hash()
+    {
+        Some("Block does not extend the local tip")
+    } else if header.transactions_root != transactions_root(&proposal.transactions) {
+        Some("Transactions root mismatch")
+    } else if header.sequenced_root
+        != next_sequenced_root(
+            &state_lock.sequenced_tree,
+            header.height,
+            &proposal.transactions,
+        )
+    {
+        Some("Sequenced root mismatch")
+    } else if has_duplicates {
+        Some("Block contains duplicate Transactions")
+    } else if !governance_is_valid {
+        Some("Invalid Governance Transaction")
+    } else if !envelopes_are_valid {
+        Some("Invalid Transaction Signature")
+    } else if block_bytes > state_lock.genesis.max_block_bytes {
+        Some("Block exceeds the size limit")
+    } else if !verify_transaction_nonces(state_lock.block_state.as_ref(), &proposal.transactions)? {
+        Some("Transaction nonces are out of order")
+    } else {
+        // the proposal's nodes are only staged and dropped after the check
+        let trie_root = state_lock.merkle_trie_root.clone();
+        let new_root = apply_transactions(
+            &mut StagedTrie::new(state_lock.block_state.as_ref()),
+            trie_root,
+            &proposal.transactions,
+        );
+        if new_root.hash != header.state_root {
+            Some("State root mismatch")
+        } else {
+            None
+        }
+    };
+    Ok(rejection)
+}
+// every valid commitment is recorded, even for proposals we won't sign,
+// so that validators signing conflicting headers are caught
+async fn detect_equivocations(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    proposal: &Block,
+) -> Result<()> {
+    let mut evidence: Vec<EquivocationEvidence> = Vec::new();
+    for commitment in proposal.commitments.clone().unwrap_or(Vec::new()) {
+        let is_valid = match deserialize_vk(&commitment.validator) {
+            Ok(commitment_vk) => {
+                state_lock
+                    .consensus_state
+                    .validators
+                    .contains(&commitment_vk)
+                    && Signature::from_slice(&commitment.signature)
+                        .and_then(|signature| {
+                            commitment_vk.verify(&proposal.to_bytes(), &signature)
+                        })
+                        .is_ok()
+            }
+            Err(_) => false,
+        };
+        if !is_valid {
+            continue;
+        }
+        if let Some(equivocation) = state_lock
+            .consensus_state
+            .record_commitment(&proposal.header, &commitment)
+        {
+            evidence.push(equivocation);
+        }
+    }
+    for equivocation in evidence {
+        if state_lock
+            .block_state
+            .insert_evidence(equivocation.clone())?
+        {
+            println!(
+                "{}",
+                format_args!(
+                    "{} Validator equivocated at height {}: {:?}",
+                    "[Warning]".yellow(),
+                    equivocation.height,
+                    equivocation.validator
+                )
+            );
+            state_lock
+                .local_gossipper
+                .gossip_equivocation_evidence(equivocation)
+                .await;
+        }
+    }
+    Ok(())
+}
+// the new trie nodes are staged and stored in the same database transaction as the Block,
+// so the stored trie always matches the latest stored root
+fn commit_finalized_block(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    block: Block,
+    certificate: Option<FinalityCertificate>,
+    new_transactions: &[Transaction],
+) -> Result<()> {
+    let trie_root = state_lock.merkle_trie_root.clone();
+    let mut trie = StagedTrie::new(state_lock.block_state.as_ref());
+    let new_root = apply_transactions(&mut trie, trie_root, new_transactions);
+    let trie_nodes = trie.into_nodes();
+    if new_root.hash != block.header.state_root {
+        bail!("Trie root does not match Block {}", block.header.height);
+    }
+    let included = sequenced_leaves(block.header.height, &block.transactions);
+    let included_hashes: Vec<GenericHash> = included.iter().map(|leaf| leaf.hash.clone()).collect();
+    state_lock
+        .block_state
+        .commit_block(block, certificate, &new_root, trie_nodes)?;
+    state_lock
+        .pool_state
+        .remove_transactions(&included_hashes)?;
+    // a local proposal for this height that lost to this Block can't finalize anymore
+    state_lock.pool_state.release_reservations()?;
+    state_lock
+        .dropped_transactions
+        .retain(|hash| !included_hashes.contains(hash));
+    for leaf in included {
+        state_lock.sequenced_tree.insert(leaf);
+    }
+    state_lock.merkle_trie_root = new_root;
+    let height = state_lock.block_state.height()?;
+    if snapshot_height(height) == Some(height) {
+        produce_snapshot(state_lock, height);
+    }
+    Ok(())
+}
+// a failed snapshot is only reported, the previous one is served until the next interval
+pub fn produce_snapshot(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    height: u32,
+) {
+    match build_snapshot(state_lock.block_state.as_ref(), height) {
+        Ok(snapshot) => {
+            println!(
+                "{}",
+                format_args!(
+                    "{} Produced snapshot at height {}: {} chunks",
+                    "[Info]".green(),
+                    height,
+                    snapshot.manifest.chunk_count
+                )
+            );
+            state_lock.snapshot = Some(snapshot);
+        }
+        Err(e) => println!(
+            "{}",
+            format_args!(
+                "{} Failed to produce snapshot at height {}: {:#}",
+                "[Warning]".yellow(),
+                height,
+                e
+            )
+        ),
+    }
+}
+// a synchronized Block is only applied if it extends our tip, matches its header
+// and carries a certificate from a quorum of the validator set at its height
+pub fn apply_certified_block(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    certified_block: CertifiedBlock,
+) -> Result<bool> {
+    let CertifiedBlock { block, certificate } = certified_block;
+    let height = block.header.height;
+    let rejection = match verify_finality_certificate(
+        &certificate,
+        &block,
+        &state_lock.consensus_state.validators,
+        state_lock.consensus_state.quorum(),
+    ) {
+        Ok(_) => block_rejection(state_lock, &block)?.map(|reason| reason.to_string()),
+        Err(e) => Some(e.to_string()),
+    };
+    if let Some(reason) = rejection {
         println!(
             "{}",
             format_args!(
-                "{} New Trie Root: {:?}",
-                "[Info]".green(),
-                state_lock.merkle_trie_root.hash
+                "{} Invalid synchronized Block #{}: {}",
+                "[Warning]".yellow(),
+                height,
+                reason
             )
         );
+        return Ok(false);
+    }
+    // the Block was verified to contain no finalized transactions
+    let transactions = block.transactions.clone();
+    commit_finalized_block(state_lock, block.clone(), Some(certificate), &transactions)?;
+    state_lock.consensus_state.apply_governance(&block);
+    state_lock.consensus_state.reinitialize();
+    state_lock.consensus_state.reset_view();
+    state_lock.consensus_state.prune_signed_headers(height);
+    println!(
+        "{}",
+        format_args!("{} Synchronized Block: {}", "[Info]".green(), height)
+    );
+    Ok(true)
+}
+// bootstraps a node without Blocks from a snapshot whose chunks were verified against
+// the manifest. the validator set is rebuilt from genesis and must have certified the
+// snapshot Block, the rebuilt trie must match its state root
+pub fn apply_snapshot(
+    state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
+    manifest: SnapshotManifest,
+    chunks: Vec<SnapshotChunk>,
+) -> Result<bool> {
+    let CertifiedBlock { block, certificate } = manifest.certified_block;
+    let height = block.header.height;
+    let mut transactions: Vec<FinalizedTransaction> = chunks
+        .into_iter()
+        .flat_map(|chunk| chunk.entries)
+        .map(|entry| entry.finalized)
+        .collect();
+    transactions.sort_by_key(|finalized| (finalized.height, finalized.position));
+    let mut consensus_state = InMemoryConsensus::from_genesis(
+        &state_lock.genesis,
+        state_lock.consensus_state.local_signing_key.clone(),
+    );
+    let rejection: Option<String> = if block.header.chain_id != state_lock.genesis.chain_id {
+        Some("Chain id mismatch".to_string())
+    } else if height <= state_lock.block_state.height()? {
+        Some("Snapshot is not ahead of the local tip".to_string())
+    } else if block.header.transactions_root != transactions_root(&block.transactions) {
+        Some("Transactions root mismatch".to_string())
+    } else if let Err(e) = replay_governance(
+        &state_lock.genesis,
+        &mut consensus_state,
+        &transactions,
+        height,
+        true,
+    ) {
+        Some(format!("{:#}", e))
+    } else if let Err(e) = verify_finality_certificate(
+        &certificate,
+        &block,
+        &consensus_state.validators,
+        consensus_state.quorum(),
+    ) {
+        Some(e.to_string())
+    } else {
+        None
+    };
+    // the trie is only rebuilt for a certified snapshot, in a separate trie
+    // that replaces the node's trie once its root matches
+    let mut snapshot_trie = StagedTrie::detached(TrieNodes::new());
+    let trie_root = match &rejection {
+        Some(_) => None,
+        None => {
+            let chain_transactions: Vec<Transaction> = transactions
+                .iter()
+                .map(|finalized| finalized.transaction.clone())
+                .collect();
+            Some(apply_transactions(
+                &mut snapshot_trie,
+                Root::empty(),
+                &chain_transactions,
+            ))
+        }
+    };
+    let trie_root = match (rejection, trie_root) {
+        (None, Some(trie_root)) if trie_root.hash == block.header.state_root => trie_root,
+        (rejection, _) => {
+            println!(
+                "{}",
+                format_args!(
+                    "{} Invalid snapshot at height {}: {}",
+                    "[Warning]".yellow(),
+                    height,
+                    rejection.unwrap_or_else(|| "State root mismatch".to_string())
+                )
+            );
+            return Ok(false);
+        }
+    };
+    consensus_state.apply_governance(&block);
+    state_lock.block_state.import_snapshot(
+        CertifiedBlock { block, certificate },
+        &transactions,
+        &trie_root,
+        snapshot_trie.into_nodes(),
+    )?;
+    state_lock.consensus_state = consensus_state;
+    state_lock.merkle_trie_root = trie_root;
+    let leaves: Vec<SequencedLeaf> = transactions
+        .iter()
+        .map(|finalized| SequencedLeaf {
+            hash: finalized.transaction.hash(),
+            height: finalized.height,
+            position: finalized.position,
+        })
+        .collect();
+    state_lock.sequenced_tree = SequencedTree::from_leaves(&leaves);
+    let mut finalized: Vec<GenericHash> = Vec::new();
+    for transaction in state_lock.pool_state.get_all_transactions()? {
+        let transaction_hash = transaction.hash();
+        if state_lock
+            .block_state
+            .contains_transaction(&transaction_hash)?
+        {
+            finalized.push(transaction_hash);
+        }
+    }
+    state_lock.pool_state.remove_transactions(&finalized)?;
+    let mut dropped = std::mem::take(&mut state_lock.dropped_transactions);
+    for hash in dropped.drain(..) {
+        if !state_lock.block_state.contains_transaction(&hash)? {
+            state_lock.dropped_transactions.push_back(hash);
+        }
     }
+    println!(
+        "{}",
+        format_args!(
+            "{} Bootstrapped from snapshot at height {} with {} Transactions",
+            "[Info]".green(),
+            height,
+            transactions.len()
+        )
+    );
+    Ok(true)
 }
 pub async fn handle_block_proposal(
     state_lock: &mut tokio::sync::RwLockWriteGuard<'_, ServerState>,
     proposal: &mut Block,
     error_response: String,
-) -> Option<String> {
+) -> Result<Option<String>> {
+    detect_equivocations(state_lock, proposal).await?;
     let early_revert: bool = match &state_lock.consensus_state.lowest_block {
         Some(v) => {
             if proposal.to_bytes() < v.clone() {
@@ -89,24 +596,43 @@ pub async fn handle_block_proposal(
         }
     };
     if early_revert {
-        return Some(error_response);
+        return Ok(Some(error_response));
+    }
+    if !verify_proposal_header(state_lock, proposal)? {
+        return Ok(Some(error_response));
     }
+    state_lock.consensus_state.pending_block = Some(proposal.clone());
     // sign the block if it has not been signed yet
     let mut is_signed = false;
     let block_commitments = proposal.commitments.clone().unwrap_or(Vec::new());
-    let mut commitment_count: u32 = 0;
+    // only one valid commitment per validator counts towards finality
+    let mut valid_commitments: Vec<BlockCommitment> = Vec::new();
+    let mut signers: Vec<VerifyingKey> = Vec::new();
     for commitment in block_commitments {
-        let commitment_vk = deserialize_vk(&commitment.validator);
-        if state_lock
+        let commitment_vk = match deserialize_vk(&commitment.validator) {
+            Ok(commitment_vk) => commitment_vk,
+            Err(_) => {
+                println!("[Err] Invalid Proposal found with malformed VK");
+                continue;
+            }
+        };
+        if signers.contains(&commitment_vk) {
+            println!(
+                "{}",
+                format_args!("{} Duplicate Commitment was Ignored", "[Warning]".yellow())
+            );
+        } else if state_lock
             .consensus_state
             .validators
             .contains(&commitment_vk)
         {
-            match commitment_vk.verify(
-                &proposal.to_bytes(),
-                &Signature::from_slice(&commitment.signature).unwrap(),
-            ) {
-                Ok(_) => commitment_count += 1,
+            match Signature::from_slice(&commitment.signature)
+                .and_then(|signature| commitment_vk.verify(&proposal.to_bytes(), &signature))
+            {
+                Ok(_) => {
+                    signers.push(commitment_vk);
+                    valid_commitments.push(commitment.clone());
+                }
                 Err(_) => {
                     println!(
                         "{}",
@@ -127,53 +653,41 @@ pub async fn handle_block_proposal(
             is_signed = true;
         }
     }
+    let commitment_count = valid_commitments.len() as u32;
     println!(
         "[Info] Commitment count for proposal: {}",
         &commitment_count
     );
-    #[cfg(not(feature = "sqlite"))]
-    let previous_block_height = state_lock.block_state.height - 1;
-    #[cfg(feature = "sqlite")]
-    let previous_block_height = state_lock.block_state.current_block_height() - 1;
-    if proposal.height != previous_block_height + 1 {
-        return Some(error_response);
-    }
-    if commitment_count >= CONSENSUS_THRESHOLD {
+    let previous_block = state_lock.block_state.latest_block()?;
+    let previous_block_height = previous_block.header.height;
+    if proposal.header.height != previous_block_height + 1 {
+        return Ok(Some(error_response));
+    }
+    if commitment_count >= state_lock.consensus_state.quorum() {
         println!(
             "{}",
             format_args!("{} Received Valid Block", "[Info]".green())
         );
-        #[cfg(not(feature = "sqlite"))]
-        state_lock
-            .block_state
-            .insert_block(proposal.height - 1, proposal.clone());
-        #[cfg(feature = "sqlite")]
-        state_lock
-            .block_state
-            .insert_block(proposal.height, proposal.clone());
-        // insert transactions into the trie
-        let root_node = Node::Root(state_lock.merkle_trie_root.clone());
-        for transaction in &proposal.transactions {
-            let mut leaf = Leaf::new(Vec::new(), Some(transaction.data.clone()));
-            leaf.hash();
-            leaf.key = leaf
-                .hash
-                .clone()
-                .unwrap()
-                .iter()
-                .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
-                .collect();
-            leaf.hash();
-            todo!("check if leaf exists and insert otherwise");
-            // currently duplicate insertion will cause an error
-            let new_root = insert_leaf(&mut state_lock.merkle_trie_state, &mut leaf, root_node);
-            root_node = Node::Root(new_root);
-        }
-        // update in-memory trie root
-        state_lock.merkle_trie_root = root_node.unwrap_as_root();
+        let certificate = FinalityCertificate {
+            height: proposal.header.height,
+            block_hash: proposal.hash(),
+            commitments: valid_commitments,
+        };
+        // the proposal was verified to contain no finalized transactions
+        let transactions = proposal.transactions.clone();
+        commit_finalized_block(
+            state_lock,
+            proposal.clone(),
+            Some(certificate),
+            &transactions,
+        )?;
         println!(
             "{}",
-            format_args!("{} Block was stored: {}", "[Info]".green(), proposal.height)
+            format_args!(
+                "{} Block was stored: {}",
+                "[Info]".green(),
+                proposal.header.height
+            )
         );
         println!(
             "{}",
@@ -183,11 +697,22 @@ pub async fn handle_block_proposal(
                 state_lock.merkle_trie_root.hash
             )
         );
+        state_lock.consensus_state.apply_governance(proposal);
+        state_lock.consensus_state.reset_view();
+        state_lock
+            .consensus_state
+            .prune_signed_headers(proposal.header.height);
         //state_lock.consensus_state.reinitialize();
     } else if !is_signed
         // && !state_lock.consensus_state.signed
         // only signing proposals for the current height
-        && (previous_block_height + 1 == proposal.height)
+        && (previous_block_height + 1 == proposal.header.height)
+        // the local key never signs two different headers in the same round,
+        // otherwise it would be slashed for equivocation
+        && state_lock
+            .block_state
+            .get_last_signed()?
+            .map_or(true, |last_signed| last_signed.permits(&proposal.header))
     {
         let mut local_sk = state_lock.consensus_state.local_signing_key.clone();
         let block_bytes = proposal.to_bytes();
@@ -204,20 +729,37 @@ pub async fn handle_block_proposal(
                 .clone(),
             timestamp: unix_timestamp,
         };
+        // stored before the signature leaves the node
+        state_lock.block_state.set_last_signed(LastSigned {
+            height: proposal.header.height,
+            round: proposal.header.round,
+            hash: proposal.hash(),
+        })?;
+        state_lock
+            .consensus_state
+            .record_commitment(&proposal.header, &commitment);
         match proposal.commitments.as_mut() {
             Some(commitments) => commitments.push(commitment),
             None => proposal.commitments = Some(vec![commitment]),
         }
         println!("[Info] Signed Block is being gossipped");
-        let last_block_unix_timestamp = state_lock
-            .block_state
-            .get_block_by_height(previous_block_height)
-            .timestamp;
-
+        let last_block_unix_timestamp = previous_block.header.timestamp;
         let _ = state_lock
             .local_gossipper
-            .gossip_pending_block(proposal.clone(), last_block_unix_timestamp)
+            .gossip_pending_block(
+                proposal.clone(),
+                last_block_unix_timestamp,
+                state_lock.consensus_state.round_duration,
+            )
             .await;
+    } else if !is_signed && previous_block_height + 1 == proposal.header.height {
+        println!(
+            "{}",
+            format_args!(
+                "{} A different Block was already signed in this round",
+                "[Warning]".yellow()
+            )
+        );
     } else {
         println!(
             "{}",
@@ -227,5 +769,42 @@ pub async fn handle_block_proposal(
             )
         );
     }
-    None
+    Ok(None)
+}
+
+#[test]
+fn test_select_transactions() {
+    use crate::state::server::InMemoryBlockStore;
+    use crate::types::TransactionEnvelope;
+    let transaction = |data: u8, priority_fee: u64, nonce: Option<u64>| Transaction {
+        data: vec![data; 10],
+        timestamp: 0,
+        governance: None,
+        envelope: nonce.map(|nonce| TransactionEnvelope {
+            sender: vec![1; 33],
+            nonce,
+            chain_id: "test".to_string(),
+            signature: vec![],
+        }),
+        priority_fee,
+    };
+    let block_state = InMemoryBlockStore::default();
+    // the higher fee comes first, equal fees keep their arrival order,
+    // a nonce that arrived before its predecessor waits for it
+    let pool = vec![
+        transaction(0, 1, None),
+        transaction(1, 5, Some(1)),
+        transaction(2, 1, None),
+        transaction(3, 2, Some(0)),
+    ];
+    let selected = select_transactions(&block_state, pool.clone(), u64::MAX).unwrap();
+    let data: Vec<u8> = selected
+        .iter()
+        .map(|transaction| transaction.data[0])
+        .collect();
+    assert_eq!(data, vec![3, 0, 2, 1]);
+    // transactions that don't fit are left for the next Block
+    let limit = (pool[0].size() + pool[3].size()) as u64;
+    let selected = select_transactions(&block_state, pool, limit).unwrap();
+    assert_eq!(selected.len(), 2);
 }
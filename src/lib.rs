@@ -2,6 +2,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 pub mod config;
 pub mod consensus;
 pub mod crypto;
+pub mod exclusion;
 pub mod gossipper;
 pub mod types;
 
@@ -1,15 +1,21 @@
 mod api;
+mod archive;
 mod config;
 mod consensus;
 mod crypto;
+mod exclusion;
 mod gossipper;
 mod handlers;
 mod state;
+mod synchronization;
 mod types;
 use api::{
-    commit, get_block, get_commitments, get_height, get_pool, get_state_root_hash, merkle_proof,
-    propose, schedule,
+    commit, evidence, exclusion_proof, get_block, get_blocks, get_certificate, get_commitments,
+    get_evidence, get_genesis, get_header, get_height, get_pool, get_snapshot_chunk,
+    get_snapshot_manifest, get_state_root_hash, get_status, get_transaction_status, get_validators,
+    merkle_proof, propose, schedule, timeout, view_change,
 };
+use archive::{export_chain, import_chain};
 use axum::{
     extract::DefaultBodyLimit,
     routing::{get, post},
@@ -17,125 +23,176 @@ use axum::{
 };
 use colored::*;
 use config::{
-    consensus::{CLEARING_PHASE_DURATION, ROUND_DURATION},
-    network::PEERS,
+    genesis::{load_signing_key, Genesis},
+    network::PeerSource,
+};
+use consensus::logic::{evaluate_commitment, verify_governance_transaction};
+use exclusion::SequencedTree;
+use gossipper::{Gossipper, Peer};
+use handlers::{
+    apply_transactions, evict_expired_transactions, next_sequenced_root, select_transactions,
 };
-use consensus::logic::{current_round, evaluate_commitment, get_committing_validator};
 use k256::ecdsa::{signature::SignerMut, Signature};
+use l2_sequencer::get_current_time;
+use patricia_trie::store::types::Root;
 use prover::generate_random_number;
-use reqwest::Client;
-use state::server::{BlockStore, InMemoryConsensus, TransactionPool};
-use std::{
-    env,
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
-use tokio::sync::RwLock;
-use types::{Block, ConsensusCommitment};
-#[allow(unused)]
-use {
-    gossipper::{docker_skip_self, Gossipper},
-    handlers::handle_synchronization_response,
-    reqwest::Response,
-};
+use state::mempool::MempoolLimits;
+use state::pruning::{pruning_loop, PruningMode};
+use state::recovery::recover_chain_state;
 #[cfg(feature = "sqlite")]
-use {
-    patricia_trie::store::{db::sql::TrieDB as MerkleTrieDB, types::Root},
-    state::server::{SqLiteBlockStore, SqLiteTransactionPool},
+use state::server::{open_database, SqLiteBlockStore, SqLiteTransactionPool};
+use state::server::{
+    BlockStore, InMemoryBlockStore, InMemoryConsensus, InMemoryTransactionPool, TransactionPool,
 };
-#[cfg(not(feature = "sqlite"))]
-use {
-    patricia_trie::store::{db::TrieDB as MerkleTrieDB, types::Root},
-    state::server::{InMemoryBlockStore, InMemoryTransactionPool},
-    std::collections::HashMap,
+use state::snapshot::{build_snapshot, snapshot_height, Snapshot};
+use state::trie::StagedTrie;
+use std::{collections::VecDeque, env, net::SocketAddr, sync::Arc, time::Duration};
+use synchronization::synchronize;
+use tokio::sync::RwLock;
+use types::{
+    transactions_root, Block, BlockHeader, ConsensusCommitment, GenericHash, TimeoutVote,
+    Timestamp, Transaction,
 };
 
 struct ServerState {
-    block_state: BlockStore,
-    pool_state: TransactionPool,
+    genesis: Genesis,
+    block_state: Box<dyn BlockStore>,
+    pool_state: Box<dyn TransactionPool>,
     consensus_state: InMemoryConsensus,
-    merkle_trie_state: MerkleTrieDB,
+    // the trie nodes are kept in the block store, proposals only stage theirs
     merkle_trie_root: Root,
+    // the hashes of all Transactions finalized up to the latest Block
+    sequenced_tree: SequencedTree,
     local_gossipper: Gossipper,
+    // hashes of the latest transactions that were evicted from the pool without being
+    // finalized, pending and finalized transactions are looked up in the pool and the Blocks
+    dropped_transactions: VecDeque<GenericHash>,
+    mempool_limits: MempoolLimits,
+    // set once no peer is ahead of the local chain
+    synchronized: bool,
+    // the latest trie snapshot, served to bootstrapping peers
+    snapshot: Option<Snapshot>,
+    pruning_mode: PruningMode,
+}
+impl ServerState {
+    // timestamp of the latest Block and the height of the next Block
+    fn chain_tip(&self) -> anyhow::Result<(Timestamp, u32)> {
+        let latest_block = self.block_state.latest_block()?;
+        Ok((
+            latest_block.header.timestamp,
+            latest_block.header.height + 1,
+        ))
+    }
 }
 
-// currently only supports mock net
-#[allow(unused)]
-async fn synchronization_loop(database: Arc<RwLock<ServerState>>) {
-    #[cfg(feature = "mock-net")]
-    {
-        let mut state_lock = database.write().await;
-        #[cfg(not(feature = "sqlite"))]
-        let next_height = state_lock.block_state.height - 1;
-        #[cfg(feature = "sqlite")]
-        let next_height = state_lock.block_state.current_block_height();
-        let gossipper = Gossipper {
-            peers: PEERS.to_vec(),
-            client: Client::new(),
-        };
-        for peer in gossipper.peers {
-            // todo: make this generic for n amount of nodes
-            let this_node = env::var("API_HOST_WITH_PORT").unwrap_or("0.0.0.0:8080".to_string());
-            if docker_skip_self(&this_node, &peer) {
-                continue;
-            }
-            let response: Option<Response> = match gossipper
-                .client
-                .get(format!("http://{}{}{}", &peer, "/get/block/", next_height))
-                .timeout(Duration::from_secs(15))
-                .send()
-                .await
-            {
-                Ok(response) => Some(response),
-                Err(_) => None,
-            };
-            match response {
-                Some(response) => {
-                    handle_synchronization_response(&mut state_lock, response, next_height).await;
-                }
-                _ => {}
+// peers added to the peers source are picked up without a restart
+async fn reload_peers(state: &Arc<RwLock<ServerState>>, peer_source: &PeerSource) {
+    match peer_source.load() {
+        Ok(peers) => {
+            let mut state_lock = state.write().await;
+            for peer in peers {
+                state_lock.local_gossipper.add_peer(peer);
             }
         }
+        Err(e) => println!(
+            "{}",
+            format_args!("{} Failed to reload peers: {:#}", "[Warning]".yellow(), e)
+        ),
     }
-    #[cfg(not(feature = "mock-net"))]
-    {
-        todo!("Implement mainnet synchronization!");
+}
+// returns whether the local chain caught up with all reachable peers
+async fn synchronization_loop(state: Arc<RwLock<ServerState>>) -> bool {
+    match synchronize(state).await {
+        Ok(caught_up) => caught_up,
+        Err(e) => {
+            println!("{}", format_args!("{} Storage: {:#}", "[Error]".red(), e));
+            false
+        }
     }
 }
-async fn consensus_loop(state: Arc<RwLock<ServerState>>) {
+async fn consensus_loop(state: Arc<RwLock<ServerState>>) -> anyhow::Result<()> {
     let unix_timestamp = get_current_time();
     let mut state_lock = state.write().await;
-    #[cfg(not(feature = "sqlite"))]
-    let last_block_unix_timestamp = state_lock
-        .block_state
-        .get_block_by_height(state_lock.block_state.height - 1)
-        .timestamp;
-    #[cfg(feature = "sqlite")]
-    let last_block_unix_timestamp = state_lock
-        .block_state
-        .get_block_by_height(state_lock.block_state.current_block_height() - 1)
-        .timestamp;
+    // Blocks are only proposed and voted on once synchronization caught up
+    if !state_lock.synchronized {
+        return Ok(());
+    }
+    let latest_block = state_lock.block_state.latest_block()?;
+    let last_block_unix_timestamp = latest_block.header.timestamp;
+    let previous_block_height = latest_block.header.height;
+    let round_duration = state_lock.consensus_state.round_duration;
+    let clearing_phase_duration = state_lock.consensus_state.clearing_phase_duration;
     // check if clearing phase of new consensus round
     if unix_timestamp
         <= last_block_unix_timestamp
-            + ((((unix_timestamp - last_block_unix_timestamp) / (ROUND_DURATION)) * ROUND_DURATION)
-                + CLEARING_PHASE_DURATION)
+            + (((unix_timestamp.saturating_sub(last_block_unix_timestamp) / (round_duration))
+                * round_duration)
+                + clearing_phase_duration)
     {
+        // a proposal that didn't finalize in the last round failed
         state_lock.consensus_state.reinitialize();
-        return;
+        state_lock.pool_state.release_reservations()?;
+        return Ok(());
+    }
+    let round = state_lock.consensus_state.round(last_block_unix_timestamp);
+    state_lock
+        .consensus_state
+        .enter_view(previous_block_height + 1, round);
+    let committing_validator = state_lock
+        .consensus_state
+        .committing_validator(last_block_unix_timestamp);
+    println!("[Info] Current round: {}", round);
+    // no Block was finalized within half a round, vote to change the view
+    let round_start = last_block_unix_timestamp
+        + (unix_timestamp.saturating_sub(last_block_unix_timestamp) / round_duration)
+            * round_duration;
+    let view_start = round_start.max(state_lock.consensus_state.last_view_change);
+    if unix_timestamp.saturating_sub(view_start) >= round_duration / 2
+        && !state_lock.consensus_state.timed_out
+        && state_lock
+            .consensus_state
+            .validators
+            .contains(&state_lock.consensus_state.local_validator)
+    {
+        let mut vote = TimeoutVote {
+            validator: state_lock
+                .consensus_state
+                .local_validator
+                .to_sec1_bytes()
+                .to_vec(),
+            chain_id: state_lock.genesis.chain_id.clone(),
+            height: previous_block_height + 1,
+            round,
+            signature: vec![],
+        };
+        let mut signing_key = state_lock.consensus_state.local_signing_key.clone();
+        let signature: Signature = signing_key.sign(&vote.to_bytes());
+        vote.signature = signature.to_bytes().to_vec();
+        println!(
+            "{}",
+            format_args!(
+                "{} Round {} timed out, gossipping Timeout Vote",
+                "[Warning]".yellow(),
+                round
+            )
+        );
+        state_lock.consensus_state.timed_out = true;
+        let _ = state_lock
+            .local_gossipper
+            .gossip_timeout_vote(vote.clone())
+            .await;
+        if let Some(certificate) = state_lock.consensus_state.add_timeout_vote(vote) {
+            state_lock
+                .consensus_state
+                .apply_view_change(last_block_unix_timestamp);
+            state_lock.pool_state.release_reservations()?;
+            let _ = state_lock
+                .local_gossipper
+                .gossip_timeout_certificate(certificate)
+                .await;
+            return Ok(());
+        }
     }
-    let committing_validator = get_committing_validator(
-        last_block_unix_timestamp,
-        state_lock.consensus_state.validators.clone(),
-    );
-    println!(
-        "[Info] Current round: {}",
-        current_round(last_block_unix_timestamp)
-    );
-    #[cfg(not(feature = "sqlite"))]
-    let previous_block_height = state_lock.block_state.height - 1;
-    #[cfg(feature = "sqlite")]
-    let previous_block_height = state_lock.block_state.current_block_height() - 1;
     if state_lock.consensus_state.local_validator == committing_validator
         && !state_lock.consensus_state.committed
     {
@@ -147,14 +204,20 @@ async fn consensus_loop(state: Arc<RwLock<ServerState>>) {
                 .to_vec(),
             (previous_block_height + 1).to_be_bytes().to_vec(),
         );
-        let commitment = ConsensusCommitment {
+        let mut commitment = ConsensusCommitment {
             validator: state_lock
                 .consensus_state
                 .local_validator
                 .to_sec1_bytes()
                 .to_vec(),
+            height: previous_block_height + 1,
+            round,
             receipt: random_zk_number,
+            signature: vec![],
         };
+        let mut signing_key = state_lock.consensus_state.local_signing_key.clone();
+        let signature: Signature = signing_key.sign(&commitment.to_bytes());
+        commitment.signature = signature.to_bytes().to_vec();
         let _ = state_lock
             .local_gossipper
             .gossip_consensus_commitment(commitment.clone())
@@ -165,41 +228,121 @@ async fn consensus_loop(state: Arc<RwLock<ServerState>>) {
         state_lock.consensus_state.committed = true;
     }
     if state_lock.consensus_state.round_winner.is_none() {
-        return;
+        return Ok(());
     }
     let proposing_validator = state_lock.consensus_state.round_winner.unwrap();
-    #[cfg(not(feature = "sqlite"))]
-    let transactions = state_lock
-        .pool_state
-        .transactions
-        .values()
-        .cloned()
+    evict_expired_transactions(&mut state_lock)?;
+    // governance transactions may have expired while they were pending
+    // and transactions may have been finalized in a Block from another proposer
+    let mut transactions: Vec<Transaction> = Vec::new();
+    // reserved transactions are part of our own proposal for this height
+    for entry in state_lock.pool_state.get_entries()? {
+        if !entry.reserved
+            && !state_lock
+                .block_state
+                .contains_transaction(&entry.transaction.hash())?
+        {
+            transactions.push(entry.transaction);
+        }
+    }
+    let transactions: Vec<Transaction> = transactions
+        .into_iter()
+        .filter(|transaction| {
+            transaction.governance.as_ref().map_or(true, |governance| {
+                verify_governance_transaction(
+                    governance,
+                    &state_lock.genesis.chain_id,
+                    &state_lock.consensus_state.validators,
+                    state_lock.consensus_state.quorum(),
+                    previous_block_height + 1,
+                )
+                .is_ok()
+            })
+        })
         .collect();
-    #[cfg(feature = "sqlite")]
-    let transactions = state_lock.pool_state.get_all_transactions();
+    let transactions = select_transactions(
+        state_lock.block_state.as_ref(),
+        transactions,
+        state_lock.genesis.max_block_bytes,
+    )?;
     if state_lock.consensus_state.local_validator == proposing_validator
         && !state_lock.consensus_state.proposed
     {
+        let parent_hash = latest_block.hash();
+        let trie_root = state_lock.merkle_trie_root.clone();
+        let state_root = apply_transactions(
+            &mut StagedTrie::new(state_lock.block_state.as_ref()),
+            trie_root,
+            &transactions,
+        )
+        .hash;
+        let sequenced_root = next_sequenced_root(
+            &state_lock.sequenced_tree,
+            previous_block_height + 1,
+            &transactions,
+        );
+        let selected: Vec<GenericHash> = transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect();
         let mut proposed_block = Block {
-            height: previous_block_height + 1,
+            header: BlockHeader {
+                height: previous_block_height + 1,
+                round,
+                chain_id: state_lock.genesis.chain_id.clone(),
+                parent_hash,
+                transactions_root: transactions_root(&transactions),
+                state_root,
+                sequenced_root,
+                proposer: proposing_validator.to_sec1_bytes().to_vec(),
+                timestamp: unix_timestamp,
+            },
             signature: None,
             transactions,
             commitments: None,
-            timestamp: unix_timestamp,
         };
         let mut signing_key = state_lock.consensus_state.local_signing_key.clone();
         let signature: Signature = signing_key.sign(&proposed_block.to_bytes());
         proposed_block.signature = Some(signature.to_bytes().to_vec());
+        state_lock.consensus_state.pending_block = Some(proposed_block.clone());
         println!(
             "{}",
             format_args!("{} Gossipping proposed Block", "[Info]".green())
         );
         let _ = state_lock
             .local_gossipper
-            .gossip_pending_block(proposed_block, last_block_unix_timestamp)
+            .gossip_pending_block(proposed_block, last_block_unix_timestamp, round_duration)
             .await;
         state_lock.consensus_state.proposed = true;
-        state_lock.pool_state.reinitialize()
+        // the transactions stay in the pool until the Block is finalized,
+        // transactions that didn't fit are picked for the next Block
+        state_lock.pool_state.reserve_transactions(&selected)?;
+    }
+    Ok(())
+}
+// the storage backend is selected with STORAGE_BACKEND, either "memory" or "sqlite",
+// sqlite is the default when the node was built with the sqlite feature
+fn open_storage() -> anyhow::Result<(Box<dyn BlockStore>, Box<dyn TransactionPool>)> {
+    #[cfg(feature = "sqlite")]
+    let default_backend = "sqlite";
+    #[cfg(not(feature = "sqlite"))]
+    let default_backend = "memory";
+    let backend = env::var("STORAGE_BACKEND").unwrap_or(default_backend.to_string());
+    match backend.as_str() {
+        "memory" => Ok((
+            Box::new(InMemoryBlockStore::default()),
+            Box::new(InMemoryTransactionPool::default()),
+        )),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => {
+            let db_path = env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string());
+            let conn = open_database(&db_path)?;
+            Ok((
+                Box::new(SqLiteBlockStore { conn: conn.clone() }),
+                Box::new(SqLiteTransactionPool::open(conn)?),
+            ))
+        }
+        _ => anyhow::bail!("Unsupported storage backend: {}", backend),
     }
 }
 #[tokio::main]
@@ -220,56 +363,193 @@ async fn main() {
             .italic()
             .magenta()
     );
-    #[cfg(feature = "sqlite")]
-    let mut block_state = {
-        let block_state: BlockStore = BlockStore {
-            db_path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-        };
-        block_state.setup();
-        block_state
+    let genesis_path = env::var("PATH_TO_GENESIS").unwrap_or("resources/genesis.json".to_string());
+    let genesis: Genesis = match Genesis::load(&genesis_path) {
+        Ok(genesis) => genesis,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to load genesis: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
     };
-    #[cfg(not(feature = "sqlite"))]
-    let mut block_state = {
-        let block_state: BlockStore = BlockStore::empty();
-        block_state
+    println!(
+        "{}",
+        format_args!(
+            "{} Chain: {}, Genesis: {}",
+            "[Info]".green(),
+            &genesis.chain_id,
+            genesis.hash_hex()
+        )
+    );
+    let (mut block_state, mut pool_state) = match open_storage() {
+        Ok(storage) => storage,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to open storage: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
     };
-    block_state.trigger_genesis(get_current_time());
-    #[cfg(not(feature = "sqlite"))]
-    let pool_state: TransactionPool = TransactionPool::empty();
-    #[cfg(feature = "sqlite")]
-    let pool_state: TransactionPool = {
-        let pool_state: TransactionPool = TransactionPool {
-            size: 0,
-            db_path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-        };
-        pool_state.setup();
-        pool_state
+    let key_path = env::var("PATH_TO_KEY").unwrap_or("resources/keys/v1.json".to_string());
+    let local_signing_key = match load_signing_key(&key_path) {
+        Ok(local_signing_key) => local_signing_key,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to load signing key: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
     };
-    let consensus_state: InMemoryConsensus = InMemoryConsensus::empty_with_default_validators();
-    #[cfg(not(feature = "sqlite"))]
-    let merkle_trie_state: MerkleTrieDB = MerkleTrieDB {
-        nodes: HashMap::new(),
+    let mut consensus_state: InMemoryConsensus =
+        InMemoryConsensus::from_genesis(&genesis, local_signing_key);
+    let merkle_trie_root: Root = match recover_chain_state(
+        &genesis,
+        block_state.as_mut(),
+        pool_state.as_mut(),
+        &mut consensus_state,
+    ) {
+        Ok(merkle_trie_root) => merkle_trie_root,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to recover chain state: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
     };
-    #[cfg(feature = "sqlite")]
-    let merkle_trie_state: MerkleTrieDB = MerkleTrieDB {
-        path: env::var("PATH_TO_DB").unwrap_or("database.sqlite".to_string()),
-        cache: None,
+    // the tree is built once and extended by every finalized Block
+    let sequenced_tree = match block_state
+        .height()
+        .and_then(|height| block_state.get_sequenced_leaves(height))
+    {
+        Ok(leaves) => SequencedTree::from_leaves(&leaves),
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!(
+                    "{} Failed to load sequenced Transactions: {:#}",
+                    "[Error]".red(),
+                    e
+                )
+            );
+            std::process::exit(1);
+        }
     };
-    #[cfg(feature = "sqlite")]
-    merkle_trie_state.setup();
-    let merkle_trie_root: Root = Root::empty();
-    let local_gossipper: Gossipper = Gossipper {
-        peers: PEERS.to_vec(),
-        client: Client::new(),
+    if !consensus_state
+        .validators
+        .contains(&consensus_state.local_validator)
+    {
+        println!(
+            "{}",
+            format_args!(
+                "{} Local key is not part of the validator set",
+                "[Warning]".yellow()
+            )
+        );
+    }
+    let mut args: Vec<String> = env::args().collect();
+    let peer_source = match PeerSource::from_args(&mut args) {
+        Ok(peer_source) => peer_source,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to load peers: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
+    };
+    let peers: Vec<Peer> = match peer_source.load() {
+        Ok(peers) => peers,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to load peers: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
+    };
+    let local_gossipper: Gossipper =
+        Gossipper::new(peers, consensus_state.local_validator, genesis.hash_hex());
+    let mempool_limits = match MempoolLimits::from_env() {
+        Ok(mempool_limits) => mempool_limits,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to load mempool limits: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
+    };
+    let pruning_mode = match PruningMode::from_env() {
+        Ok(pruning_mode) => pruning_mode,
+        Err(e) => {
+            println!(
+                "{}",
+                format_args!("{} Failed to load pruning mode: {:#}", "[Error]".red(), e)
+            );
+            std::process::exit(1);
+        }
+    };
+    // snapshots are kept in memory and produced again after a restart
+    let snapshot: Option<Snapshot> = match block_state.height().ok().and_then(snapshot_height) {
+        Some(height) => match build_snapshot(block_state.as_ref(), height) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                println!(
+                    "{}",
+                    format_args!(
+                        "{} Failed to produce snapshot at height {}: {:#}",
+                        "[Warning]".yellow(),
+                        height,
+                        e
+                    )
+                );
+                None
+            }
+        },
+        None => None,
     };
     let shared_state: Arc<RwLock<ServerState>> = Arc::new(RwLock::new(ServerState {
+        genesis,
         block_state,
         pool_state,
         consensus_state,
-        merkle_trie_state,
         merkle_trie_root,
+        sequenced_tree,
         local_gossipper,
+        dropped_transactions: VecDeque::new(),
+        mempool_limits,
+        synchronized: false,
+        snapshot,
+        pruning_mode,
     }));
+    // `export <path> [--snapshot]` and `import <path>` work on the storage and exit
+    if let Some(command) = args.get(1) {
+        let result = match (command.as_str(), args.get(2)) {
+            ("export", Some(path)) => {
+                let include_snapshot = args[3..].iter().any(|arg| arg == "--snapshot");
+                export_chain(Arc::clone(&shared_state), path, include_snapshot).await
+            }
+            ("import", Some(path)) => import_chain(Arc::clone(&shared_state), path).await,
+            _ => Err(anyhow::anyhow!(
+                "Usage: export <path> [--snapshot] | import <path>"
+            )),
+        };
+        match result {
+            Ok(()) => std::process::exit(0),
+            Err(e) => {
+                println!(
+                    "{}",
+                    format_args!("{} {} failed: {:#}", "[Error]".red(), command, e)
+                );
+                std::process::exit(1);
+            }
+        }
+    }
     let host_with_port = env::var("API_HOST_WITH_PORT").unwrap_or("0.0.0.0:8080".to_string());
     let formatted_msg = format!(
         "{}{}",
@@ -282,9 +562,11 @@ async fn main() {
         let shared_state = Arc::clone(&shared_state);
         async move {
             loop {
-                // for now the loop syncs one block at a time, this can be optimized
-                synchronization_loop(Arc::clone(&shared_state)).await;
-                tokio::time::sleep(Duration::from_secs(120)).await;
+                reload_peers(&shared_state, &peer_source).await;
+                // keep downloading while behind, then only check for peers that moved ahead
+                let caught_up = synchronization_loop(Arc::clone(&shared_state)).await;
+                let interval = if caught_up { 120 } else { 1 };
+                tokio::time::sleep(Duration::from_secs(interval)).await;
             }
         }
     });
@@ -292,30 +574,76 @@ async fn main() {
         let shared_state = Arc::clone(&shared_state);
         async move {
             loop {
-                consensus_loop(Arc::clone(&shared_state)).await;
+                if let Err(e) = consensus_loop(Arc::clone(&shared_state)).await {
+                    println!("{}", format_args!("{} Storage: {:#}", "[Error]".red(), e));
+                }
                 tokio::time::sleep(Duration::from_secs(10)).await;
             }
         }
     });
+    // the chain is pruned in the background, commits continue while the retained trie is marked
+    let pruning_task = tokio::spawn({
+        let shared_state = Arc::clone(&shared_state);
+        async move {
+            loop {
+                match pruning_loop(Arc::clone(&shared_state)).await {
+                    Ok(Some(prune_height)) => println!(
+                        "{}",
+                        format_args!(
+                            "{} Pruned Blocks and state up to height {}",
+                            "[Info]".green(),
+                            prune_height
+                        )
+                    ),
+                    Ok(None) => {}
+                    Err(e) => println!(
+                        "{}",
+                        format_args!("{} Failed to prune: {:#}", "[Warning]".yellow(), e)
+                    ),
+                }
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+        }
+    });
     let api_task = tokio::spawn({
         async move {
             let api = Router::new()
                 .route("/get/pool", get(get_pool))
                 .route("/get/commitments", get(get_commitments))
                 .route("/get/block/:height", get(get_block))
+                .route("/get/header/:height", get(get_header))
+                .route("/get/blocks/:from/:to", get(get_blocks))
+                .route("/get/certificate/:height", get(get_certificate))
                 .route("/get/height", get(get_height))
+                .route("/get/status", get(get_status))
+                .route("/get/snapshot", get(get_snapshot_manifest))
+                .route("/get/snapshot/:index", get(get_snapshot_chunk))
+                .route("/get/genesis", get(get_genesis))
+                .route("/get/evidence", get(get_evidence))
+                .route("/get/validators/:height", get(get_validators))
                 .route("/get/state_root_hash", get(get_state_root_hash))
+                .route("/tx/:hash", get(get_transaction_status))
                 .route("/schedule", post(schedule))
                 .route("/commit", post(commit))
                 .route("/propose", post(propose))
+                .route("/timeout", post(timeout))
+                .route("/view_change", post(view_change))
+                .route("/evidence", post(evidence))
                 .route("/merkle_proof", post(merkle_proof))
+                .route("/exclusion_proof", post(exclusion_proof))
                 .layer(DefaultBodyLimit::max(10000000))
                 .layer(Extension(shared_state));
 
             let listener = tokio::net::TcpListener::bind(&host_with_port)
                 .await
                 .unwrap();
-            axum::serve(listener, api).await.unwrap();
+            // the client address counts towards the per-client pool quota
+            axum::serve(
+                listener,
+                api.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+            .unwrap();
         }
     });
     tokio::select! {
@@ -331,6 +659,12 @@ async fn main() {
                 Err(e) => println!("{}", format_args!("{} Consensus task failed with error: {}", "[Error]".red(), e))
             }
         },
+        pruning_task_res = pruning_task => {
+            match pruning_task_res {
+                Ok(_) => println!("{}", format_args!("{} Pruning task concluded without error", "[Warning]".yellow())),
+                Err(e) => println!("{}", format_args!("{} Pruning task failed with error: {}", "[Error]".red(), e))
+            }
+        },
         api_task_res = api_task => {
             match api_task_res{
                 Ok(_) => println!("{}", format_args!("{} API task concluded without error", "[Warning]".yellow())),
@@ -339,10 +673,3 @@ async fn main() {
         }
     }
 }
-pub fn get_current_time() -> u32 {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    since_the_epoch.as_secs() as u32
-}
@@ -0,0 +1,234 @@
+use crate::state::server::PoolEntry;
+use crate::types::{GenericHash, GenericPublicKey, Timestamp};
+use anyhow::{bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+// node-local limits for pending transactions, they keep a single client
+// from filling the pool and stalling block production
+#[derive(Debug, Clone)]
+pub struct MempoolLimits {
+    pub max_transactions: usize,
+    pub max_bytes: usize,
+    // pending transactions per signing sender and per client address
+    pub max_per_sender: usize,
+    pub max_per_origin: usize,
+    // seconds a transaction may wait in the pool
+    pub transaction_ttl: u32,
+}
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_transactions: 10_000,
+            max_bytes: 50_000_000,
+            max_per_sender: 64,
+            max_per_origin: 1_000,
+            transaction_ttl: 3_600,
+        }
+    }
+}
+impl MempoolLimits {
+    // every limit can be overridden with an environment variable
+    pub fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            max_transactions: env_or("MEMPOOL_MAX_TRANSACTIONS", defaults.max_transactions)?,
+            max_bytes: env_or("MEMPOOL_MAX_BYTES", defaults.max_bytes)?,
+            max_per_sender: env_or("MEMPOOL_MAX_PER_SENDER", defaults.max_per_sender)?,
+            max_per_origin: env_or("MEMPOOL_MAX_PER_ORIGIN", defaults.max_per_origin)?,
+            transaction_ttl: env_or("MEMPOOL_TRANSACTION_TTL", defaults.transaction_ttl)?,
+        })
+    }
+    // rejects the entry if admitting it would exceed a limit,
+    // the pool must not contain expired transactions
+    pub fn check_admission(&self, usage: &PoolUsage, candidate: &PoolEntry) -> Result<()> {
+        if usage.transactions() >= self.max_transactions {
+            bail!(
+                "Transaction pool is full: {} transactions",
+                self.max_transactions
+            );
+        }
+        if usage.bytes + candidate.transaction.size() > self.max_bytes {
+            bail!("Transaction pool is full: {} bytes", self.max_bytes);
+        }
+        if let Some(envelope) = &candidate.transaction.envelope {
+            let pending = usage.per_sender.get(&envelope.sender).copied().unwrap_or(0);
+            if pending >= self.max_per_sender {
+                bail!("Sender has {} pending transactions", pending);
+            }
+        }
+        if let Some(origin) = &candidate.origin {
+            let pending = usage.per_origin.get(origin).copied().unwrap_or(0);
+            if pending >= self.max_per_origin {
+                bail!("Client has {} pending transactions", pending);
+            }
+        }
+        Ok(())
+    }
+    // hashes of the entries that waited longer than the ttl,
+    // reserved entries are kept until their proposal finalized or failed
+    pub fn expired(&self, entries: &[PoolEntry], now: Timestamp) -> Vec<GenericHash> {
+        entries
+            .iter()
+            .filter(|entry| !entry.reserved)
+            .filter(|entry| now.saturating_sub(entry.received_at) > self.transaction_ttl)
+            .map(|entry| entry.transaction.hash())
+            .collect()
+    }
+}
+// running totals of the pool, kept next to it so that admission doesn't read every entry.
+// entries are remembered by hash, as a finalized Block may remove them from the storage first
+#[derive(Debug, Default, Clone)]
+pub struct PoolUsage {
+    pub bytes: usize,
+    pub per_sender: HashMap<GenericPublicKey, usize>,
+    pub per_origin: HashMap<String, usize>,
+    pending_nonces: HashSet<(GenericPublicKey, u64)>,
+    entries: HashMap<GenericHash, EntryUsage>,
+}
+#[derive(Debug, Clone)]
+struct EntryUsage {
+    bytes: usize,
+    sender_nonce: Option<(GenericPublicKey, u64)>,
+    origin: Option<String>,
+}
+impl PoolUsage {
+    pub fn transactions(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_nonce_pending(&self, sender: &GenericPublicKey, nonce: u64) -> bool {
+        self.pending_nonces.contains(&(sender.clone(), nonce))
+    }
+    pub fn add(&mut self, entry: &PoolEntry) {
+        let entry_usage = EntryUsage {
+            bytes: entry.transaction.size(),
+            sender_nonce: entry
+                .transaction
+                .envelope
+                .as_ref()
+                .map(|envelope| (envelope.sender.clone(), envelope.nonce)),
+            origin: entry.origin.clone(),
+        };
+        let previous = self
+            .entries
+            .insert(entry.transaction.hash(), entry_usage.clone());
+        if previous.is_some() {
+            return;
+        }
+        self.bytes += entry_usage.bytes;
+        if let Some((sender, nonce)) = entry_usage.sender_nonce {
+            *self.per_sender.entry(sender.clone()).or_insert(0) += 1;
+            self.pending_nonces.insert((sender, nonce));
+        }
+        if let Some(origin) = entry_usage.origin {
+            *self.per_origin.entry(origin).or_insert(0) += 1;
+        }
+    }
+    pub fn remove(&mut self, hash: &GenericHash) {
+        let entry_usage = match self.entries.remove(hash) {
+            Some(entry_usage) => entry_usage,
+            None => return,
+        };
+        self.bytes -= entry_usage.bytes;
+        if let Some((sender, nonce)) = entry_usage.sender_nonce {
+            decrement(&mut self.per_sender, &sender);
+            self.pending_nonces.remove(&(sender, nonce));
+        }
+        if let Some(origin) = entry_usage.origin {
+            decrement(&mut self.per_origin, &origin);
+        }
+    }
+}
+fn decrement<K: std::hash::Hash + Eq>(counters: &mut HashMap<K, usize>, key: &K) {
+    if let Some(count) = counters.get_mut(key) {
+        *count -= 1;
+        if *count == 0 {
+            counters.remove(key);
+        }
+    }
+}
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> Result<T> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .ok()
+            .with_context(|| format!("Malformed value for {}: {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+fn entry(data: Vec<u8>, received_at: Timestamp, origin: &str) -> PoolEntry {
+    PoolEntry {
+        transaction: crate::types::Transaction {
+            data,
+            timestamp: 0,
+            governance: None,
+            envelope: None,
+            priority_fee: 0,
+        },
+        received_at,
+        origin: Some(origin.to_string()),
+        reserved: false,
+    }
+}
+#[test]
+fn test_mempool_admission() {
+    let limits = MempoolLimits {
+        max_transactions: 3,
+        max_bytes: 1_000,
+        max_per_sender: 1,
+        max_per_origin: 2,
+        transaction_ttl: 60,
+    };
+    let mut usage = PoolUsage::default();
+    usage.add(&entry(vec![0], 0, "a"));
+    usage.add(&entry(vec![1], 0, "a"));
+    // the client reached its quota, others are still admitted
+    assert!(limits
+        .check_admission(&usage, &entry(vec![2], 0, "a"))
+        .is_err());
+    assert!(limits
+        .check_admission(&usage, &entry(vec![2], 0, "b"))
+        .is_ok());
+    assert!(limits
+        .check_admission(&usage, &entry(vec![0; 1_000], 0, "b"))
+        .is_err());
+    usage.add(&entry(vec![2], 0, "b"));
+    assert!(limits
+        .check_admission(&usage, &entry(vec![3], 0, "c"))
+        .is_err());
+    // removed entries free their quota
+    usage.remove(&entry(vec![0], 0, "a").transaction.hash());
+    assert!(limits
+        .check_admission(&usage, &entry(vec![3], 0, "a"))
+        .is_ok());
+    // the same sender can't queue more than its quota
+    let signed = |nonce: u64| {
+        let mut signed = entry(vec![4], 0, "c");
+        signed.transaction.envelope = Some(crate::types::TransactionEnvelope {
+            sender: vec![1; 33],
+            nonce,
+            chain_id: "test".to_string(),
+            signature: vec![],
+        });
+        signed
+    };
+    let mut usage = PoolUsage::default();
+    usage.add(&signed(0));
+    assert!(usage.is_nonce_pending(&vec![1; 33], 0));
+    assert!(limits.check_admission(&usage, &signed(1)).is_err());
+    assert!(limits
+        .check_admission(&usage, &entry(vec![5], 0, "c"))
+        .is_ok());
+}
+#[test]
+fn test_mempool_expiry() {
+    let limits = MempoolLimits::default();
+    let entries = vec![entry(vec![0], 0, "a"), entry(vec![1], 3_000, "a")];
+    assert!(limits.expired(&entries, 3_600).is_empty());
+    assert_eq!(
+        limits.expired(&entries, 3_601),
+        vec![entries[0].transaction.hash()]
+    );
+}
@@ -1 +1,6 @@
+pub mod mempool;
+pub mod pruning;
+pub mod recovery;
 pub mod server;
+pub mod snapshot;
+pub mod trie;
@@ -0,0 +1,220 @@
+use crate::{
+    state::{
+        mempool::env_or,
+        server::BlockStore,
+        snapshot::{snapshot_height, SNAPSHOT_INTERVAL},
+        trie::{mark_reachable, TrieNodes},
+    },
+    ServerState,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{env, sync::Arc};
+use tokio::sync::RwLock;
+
+// full Blocks are kept for the latest `retained_blocks` heights, older Blocks are reduced
+// to their headers and certificates. the archive mode keeps everything
+#[derive(Debug, Clone, PartialEq)]
+pub enum PruningMode {
+    Archive,
+    Prune { retained_blocks: u32 },
+}
+impl PruningMode {
+    // PRUNING_MODE is either "archive" (the default) or "prune",
+    // PRUNING_RETAINED_BLOCKS overrides the number of retained Blocks
+    pub fn from_env() -> Result<Self> {
+        let mode = env::var("PRUNING_MODE").unwrap_or("archive".to_string());
+        match mode.as_str() {
+            "archive" => Ok(Self::Archive),
+            "prune" => {
+                let retained_blocks: u32 = env_or("PRUNING_RETAINED_BLOCKS", 1_000)?;
+                // the Blocks after the latest snapshot must stay available to peers
+                if retained_blocks < SNAPSHOT_INTERVAL {
+                    bail!(
+                        "PRUNING_RETAINED_BLOCKS must be at least {}",
+                        SNAPSHOT_INTERVAL
+                    );
+                }
+                Ok(Self::Prune { retained_blocks })
+            }
+            _ => bail!("Unsupported pruning mode: {}", mode),
+        }
+    }
+}
+// Blocks are pruned up to a snapshot height, so that
+// the pruned height is always below the latest snapshot
+pub fn prune_height(height: u32, retained_blocks: u32) -> Option<u32> {
+    snapshot_height(height.saturating_sub(retained_blocks))
+}
+// the trie roots a run keeps, from the pruned height up to the latest Block when it started
+pub struct PruningRun {
+    base_height: u32,
+    prune_height: u32,
+    height: u32,
+}
+// returns None if the chain was already pruned up to this height
+pub fn prepare_pruning(
+    block_state: &dyn BlockStore,
+    prune_height: u32,
+) -> Result<Option<PruningRun>> {
+    let base_height = block_state.get_base_height()?.unwrap_or(0);
+    if base_height >= prune_height {
+        return Ok(None);
+    }
+    Ok(Some(PruningRun {
+        base_height,
+        prune_height,
+        height: block_state.height()?,
+    }))
+}
+// copies the trie nodes reachable from the root of the Block at this height
+pub fn mark_root(
+    block_state: &dyn BlockStore,
+    height: u32,
+    reachable: &mut TrieNodes,
+) -> Result<()> {
+    let root = block_state
+        .get_trie_root_by_height(height)?
+        .ok_or_else(|| anyhow!("Trie root is missing: {}", height))?;
+    mark_reachable(block_state, &root, reachable)
+}
+// marks the roots of the Blocks finalized during the run, then prunes the Blocks and
+// keeps only the marked trie nodes in the same write
+pub fn finish_pruning(
+    block_state: &mut dyn BlockStore,
+    run: &PruningRun,
+    mut reachable: TrieNodes,
+) -> Result<()> {
+    if block_state.get_base_height()?.unwrap_or(0) != run.base_height {
+        bail!("Snapshot base changed while pruning");
+    }
+    for height in (run.height + 1)..=block_state.height()? {
+        mark_root(block_state, height, &mut reachable)?;
+    }
+    block_state.prune_blocks(run.prune_height, reachable)
+}
+// prunes once the retention window passed the next snapshot height, returns the pruned height.
+// stored nodes are only removed by pruning or a snapshot import, so the retained roots are
+// marked one at a time and commits continue in between. only the swap holds the write lock
+pub async fn pruning_loop(shared_state: Arc<RwLock<ServerState>>) -> Result<Option<u32>> {
+    let run = {
+        let state_lock = shared_state.read().await;
+        let retained_blocks = match state_lock.pruning_mode {
+            PruningMode::Archive => return Ok(None),
+            PruningMode::Prune { retained_blocks } => retained_blocks,
+        };
+        let prune_height = match prune_height(state_lock.block_state.height()?, retained_blocks) {
+            Some(prune_height) => prune_height,
+            None => return Ok(None),
+        };
+        match prepare_pruning(state_lock.block_state.as_ref(), prune_height)? {
+            Some(run) => run,
+            None => return Ok(None),
+        }
+    };
+    let mut reachable = TrieNodes::new();
+    for height in run.prune_height..=run.height {
+        let state_lock = shared_state.read().await;
+        mark_root(state_lock.block_state.as_ref(), height, &mut reachable)?;
+    }
+    let mut state_lock = shared_state.write().await;
+    finish_pruning(state_lock.block_state.as_mut(), &run, reachable)?;
+    Ok(Some(run.prune_height))
+}
+
+#[test]
+fn test_prune_height() {
+    assert_eq!(prune_height(SNAPSHOT_INTERVAL, SNAPSHOT_INTERVAL), None);
+    assert_eq!(
+        prune_height(SNAPSHOT_INTERVAL * 3 - 1, SNAPSHOT_INTERVAL),
+        Some(SNAPSHOT_INTERVAL)
+    );
+    assert_eq!(
+        prune_height(SNAPSHOT_INTERVAL * 3, SNAPSHOT_INTERVAL),
+        Some(SNAPSHOT_INTERVAL * 2)
+    );
+}
+#[cfg(not(feature = "sqlite"))]
+#[test]
+fn test_prune_chain() {
+    use crate::{
+        config::genesis::Genesis, handlers::apply_transactions, state::server::InMemoryBlockStore,
+        state::trie::StagedTrie, types::Transaction, Root,
+    };
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let mut block_state = InMemoryBlockStore::default();
+    block_state
+        .commit_block(genesis.block(), None, &Root::empty(), TrieNodes::new())
+        .unwrap();
+    let mut trie_root = Root::empty();
+    let mut commit_blocks = |block_state: &mut InMemoryBlockStore,
+                             heights: std::ops::RangeInclusive<u32>| {
+        for height in heights {
+            let mut block = genesis.block();
+            block.header.height = height;
+            block.transactions = (0..2u8)
+                .map(|i| Transaction {
+                    data: vec![height as u8, i],
+                    timestamp: 0,
+                    governance: None,
+                    envelope: None,
+                    priority_fee: 0,
+                })
+                .collect();
+            let mut trie = StagedTrie::new(&*block_state);
+            trie_root = apply_transactions(&mut trie, trie_root.clone(), &block.transactions);
+            let trie_nodes = trie.into_nodes();
+            block.header.state_root = trie_root.hash.clone();
+            block_state
+                .commit_block(block, None, &trie_root, trie_nodes)
+                .unwrap();
+        }
+        trie_root.clone()
+    };
+    commit_blocks(&mut block_state, 1..=4u32);
+    let run = prepare_pruning(&block_state, 2).unwrap().unwrap();
+    let mut reachable = TrieNodes::new();
+    for height in 2..=4 {
+        mark_root(&block_state, height, &mut reachable).unwrap();
+    }
+    // a Block finalized while the roots were marked is marked before the swap
+    let trie_root = commit_blocks(&mut block_state, 5..=5u32);
+    // a node that no retained root reaches is dropped
+    let unreachable = block_state.trie_nodes.values().next().unwrap().clone();
+    block_state.trie_nodes.insert(vec![0xff], unreachable);
+    finish_pruning(&mut block_state, &run, reachable).unwrap();
+    assert!(!block_state.trie_nodes.contains_key(&[0xffu8][..]));
+    // headers of pruned Blocks are kept, their bodies and trie roots are not
+    assert!(block_state.get_block_by_height(2).unwrap().is_none());
+    assert!(block_state.get_header_by_height(1).unwrap().is_some());
+    assert!(block_state.get_trie_root_by_height(1).unwrap().is_none());
+    assert!(block_state.get_block_by_height(0).unwrap().is_some());
+    // every finalized Transaction is still known and every retained root can be proven against
+    assert_eq!(block_state.get_finalized_transactions(5).unwrap().len(), 10);
+    let latest_key = block_state.latest_block().unwrap().transactions[0].key();
+    assert!(patricia_trie::merkle::merkle_proof(
+        &mut StagedTrie::new(&block_state),
+        latest_key,
+        patricia_trie::store::types::Node::Root(trie_root),
+    )
+    .is_some());
+    let retained_root = block_state.get_trie_root_by_height(2).unwrap().unwrap();
+    let (_, base_transactions) = block_state.get_snapshot_base().unwrap().unwrap();
+    assert!(patricia_trie::merkle::merkle_proof(
+        &mut StagedTrie::new(&block_state),
+        base_transactions[0].transaction.key(),
+        patricia_trie::store::types::Node::Root(retained_root),
+    )
+    .is_some());
+    // pruning again up to the same height does nothing
+    assert!(prepare_pruning(&block_state, 2).unwrap().is_none());
+    let run = prepare_pruning(&block_state, 4).unwrap().unwrap();
+    let mut reachable = TrieNodes::new();
+    for height in 4..=5 {
+        mark_root(&block_state, height, &mut reachable).unwrap();
+    }
+    finish_pruning(&mut block_state, &run, reachable).unwrap();
+    let (base_height, base_transactions) = block_state.get_snapshot_base().unwrap().unwrap();
+    assert_eq!(base_height, 4);
+    assert_eq!(base_transactions.len(), 8);
+    assert_eq!(block_state.get_finalized_transactions(5).unwrap().len(), 10);
+}
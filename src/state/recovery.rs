@@ -0,0 +1,144 @@
+use crate::{
+    config::genesis::Genesis,
+    handlers::apply_transactions,
+    state::server::{BlockStore, InMemoryConsensus, TransactionPool},
+    state::snapshot::replay_governance,
+    state::trie::{StagedTrie, TrieNodes},
+    types::{GenericHash, Transaction},
+    Root,
+};
+use anyhow::{anyhow, bail, Result};
+use colored::Colorize;
+
+// resumes from the chain in the store, an empty store starts at the genesis Block.
+// returns the trie root after the latest Block
+pub fn recover_chain_state(
+    genesis: &Genesis,
+    block_state: &mut dyn BlockStore,
+    pool_state: &mut dyn TransactionPool,
+    consensus_state: &mut InMemoryConsensus,
+) -> Result<Root> {
+    let genesis_block = genesis.block();
+    match block_state.get_block_by_height(0)? {
+        Some(stored_genesis) => {
+            if stored_genesis.hash() != genesis_block.hash() {
+                bail!("The database was created for a different genesis");
+            }
+        }
+        None => {
+            let trie_root = Root::empty();
+            block_state.commit_block(genesis_block, None, &trie_root, TrieNodes::new())?;
+            return Ok(trie_root);
+        }
+    }
+    let latest_block = block_state.latest_block()?;
+    let stored_root = block_state.trie_root()?;
+    let root_is_consistent = stored_root
+        .as_ref()
+        .is_some_and(|stored_root| stored_root.hash == latest_block.header.state_root);
+    if !root_is_consistent {
+        println!(
+            "{}",
+            format_args!(
+                "{} Stored trie root does not match Block {}, re-deriving it",
+                "[Warning]".yellow(),
+                latest_block.header.height
+            )
+        );
+    }
+    // the validator set is rebuilt from the governance transactions,
+    // the trie only if the stored root can't be trusted.
+    // Blocks up to the snapshot base, imported or pruned, are replaced by its transactions
+    let snapshot_base = block_state.get_snapshot_base()?;
+    let first_height = match &snapshot_base {
+        Some((base_height, transactions)) => {
+            replay_governance(
+                genesis,
+                consensus_state,
+                transactions,
+                base_height + 1,
+                false,
+            )?;
+            base_height + 1
+        }
+        None => 1,
+    };
+    for height in first_height..=latest_block.header.height {
+        let block = block_state
+            .get_block_by_height(height)?
+            .ok_or_else(|| anyhow!("Block is missing: {}", height))?;
+        consensus_state.apply_governance(&block);
+    }
+    let mut trie_root = Root::empty();
+    if !root_is_consistent {
+        let transactions: Vec<Transaction> = block_state
+            .get_finalized_transactions(latest_block.header.height)?
+            .into_iter()
+            .map(|finalized| finalized.transaction)
+            .collect();
+        let mut trie = StagedTrie::detached(TrieNodes::new());
+        trie_root = apply_transactions(&mut trie, trie_root, &transactions);
+        block_state.replace_trie_nodes(trie.into_nodes())?;
+    }
+    let trie_root = match stored_root {
+        Some(stored_root) if root_is_consistent => stored_root,
+        _ => {
+            if trie_root.hash != latest_block.header.state_root {
+                println!(
+                    "{}",
+                    format_args!(
+                        "{} Re-derived trie root does not match Block {}",
+                        "[Warning]".yellow(),
+                        latest_block.header.height
+                    )
+                );
+            }
+            trie_root
+        }
+    };
+    // transactions can't be finalized and pending at once
+    let mut finalized: Vec<GenericHash> = Vec::new();
+    for transaction in pool_state.get_all_transactions()? {
+        let transaction_hash = transaction.hash();
+        if block_state.contains_transaction(&transaction_hash)? {
+            finalized.push(transaction_hash);
+        }
+    }
+    pool_state.remove_transactions(&finalized)?;
+    // proposals of the previous run can't finalize anymore
+    pool_state.release_reservations()?;
+    println!(
+        "{}",
+        format_args!(
+            "{} Resuming at height {} with {} pending Transactions",
+            "[Info]".green(),
+            latest_block.header.height,
+            pool_state.get_all_transactions()?.len()
+        )
+    );
+    Ok(trie_root)
+}
+#[test]
+fn test_recover_chain_state() {
+    use crate::{
+        crypto::ecdsa::Keypair,
+        state::server::{InMemoryBlockStore, InMemoryTransactionPool},
+    };
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let mut block_state = InMemoryBlockStore::default();
+    let mut pool_state = InMemoryTransactionPool::default();
+    let mut consensus_state = InMemoryConsensus::from_genesis(&genesis, Keypair::new().sk);
+    let mut recover = |genesis: &Genesis, block_state: &mut InMemoryBlockStore| {
+        recover_chain_state(genesis, block_state, &mut pool_state, &mut consensus_state)
+    };
+    // an empty store starts at the genesis Block
+    let trie_root = recover(&genesis, &mut block_state).unwrap();
+    assert_eq!(trie_root.hash, genesis.block().header.state_root);
+    assert_eq!(block_state.height().unwrap(), 0);
+    // a restart resumes from the stored genesis Block
+    assert!(recover(&genesis, &mut block_state).is_ok());
+    // a store that was created for a different genesis is rejected
+    let mut foreign_genesis = genesis.clone();
+    foreign_genesis.timestamp += 1;
+    assert!(recover(&foreign_genesis, &mut block_state).is_err());
+}
@@ -1,296 +1,1018 @@
+use crate::state::trie::TrieNodes;
+use crate::Root;
 use crate::{
-    config::consensus::{
-        v1_sk_deserialized, v1_vk_deserialized, v2_sk_deserialized, v3_sk_deserialized,
-        v3_vk_deserialized, v4_sk_deserialized, v4_vk_deserialized,
+    config::genesis::Genesis,
+    consensus::logic::{bft_quorum, current_round, get_committing_validator},
+    crypto::ecdsa::deserialize_vk,
+    exclusion::SequencedLeaf,
+    get_current_time,
+    state::mempool::PoolUsage,
+    types::{
+        Block, BlockCommitment, BlockHeader, CertifiedBlock, ConsensusCommitment,
+        EquivocationEvidence, FinalityCertificate, FinalizedTransaction, GenericHash,
+        GenericPublicKey, LastSigned, SignedHeader, TimeoutCertificate, TimeoutVote, Timestamp,
+        Transaction, ValidatorSet, ValidatorSetChange,
     },
-    types::{Block, ConsensusCommitment, Timestamp, Transaction},
 };
+#[cfg(feature = "sqlite")]
+use anyhow::bail;
+use anyhow::{anyhow, Result};
+use colored::Colorize;
 use k256::ecdsa::{SigningKey, VerifyingKey};
+use patricia_trie::store::types::Node;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{BTreeMap, HashMap, HashSet};
 #[cfg(feature = "sqlite")]
-use rusqlite::{params, Connection};
-#[cfg(not(feature = "sqlite"))]
-use std::collections::HashMap;
-use std::env;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-pub trait InMemoryBlockStore {
-    fn empty() -> Self;
-    fn trigger_genesis(&mut self, timestamp: Timestamp);
-    fn insert_block(&mut self, previous_height: u32, block: Block);
-    fn get_block_by_height(&self, height: u32) -> Block;
+// storage for finalized Blocks and everything derived from them,
+// heights are Block heights, the genesis Block is stored at height 0
+pub trait BlockStore: Send + Sync {
+    // writes the Block, its certificate, the trie root after applying it and the new trie nodes
+    // at once, stores that share a database with the pool also remove the included transactions
+    fn commit_block(
+        &mut self,
+        block: Block,
+        certificate: Option<FinalityCertificate>,
+        trie_root: &Root,
+        trie_nodes: TrieNodes,
+    ) -> Result<()>;
+    fn get_block_by_height(&self, height: u32) -> Result<Option<Block>>;
+    // headers are kept after their Block was pruned
+    fn get_header_by_height(&self, height: u32) -> Result<Option<BlockHeader>>;
+    // height of the latest Block
+    fn height(&self) -> Result<u32>;
+    fn get_certificate_by_height(&self, height: u32) -> Result<Option<FinalityCertificate>>;
+    // trie root after applying the Block at this height,
+    // earlier roots stay valid as the trie only grows
+    fn get_trie_root_by_height(&self, height: u32) -> Result<Option<Root>>;
+    fn get_trie_node(&self, key: &[u8]) -> Result<Option<Node>>;
+    // replaces every trie node, used when the trie was re-derived from the Transactions
+    fn replace_trie_nodes(&mut self, trie_nodes: TrieNodes) -> Result<()>;
+    // returns false if evidence against the validator at this height and round is already known
+    fn insert_evidence(&mut self, evidence: EquivocationEvidence) -> Result<bool>;
+    fn get_evidence(&self) -> Result<Vec<EquivocationEvidence>>;
+    // the latest header the local validator signed, must be stored before its signature is sent
+    fn get_last_signed(&self) -> Result<Option<LastSigned>>;
+    fn set_last_signed(&mut self, last_signed: LastSigned) -> Result<()>;
+    // whether a transaction with this hash was finalized
+    fn contains_transaction(&self, hash: &GenericHash) -> Result<bool>;
+    // height of the Block that finalized the transaction and its position in that Block
+    fn get_transaction_location(&self, hash: &GenericHash) -> Result<Option<(u32, u32)>>;
+    // all Transactions finalized up to this height with their locations, sorted by hash
+    fn get_sequenced_leaves(&self, height: u32) -> Result<Vec<SequencedLeaf>>;
+    // nonce of the next signed transaction from this sender
+    fn get_next_nonce(&self, sender: &GenericPublicKey) -> Result<u64>;
+    // a node that bootstrapped from a snapshot has no Blocks below the snapshot Block,
+    // the Transactions finalized before it are indexed and kept in chain order instead.
+    // Blocks of a shorter local chain are reduced to their headers like pruned Blocks,
+    // the trie is replaced by the one rebuilt from the snapshot
+    fn import_snapshot(
+        &mut self,
+        certified_block: CertifiedBlock,
+        transactions: &[FinalizedTransaction],
+        trie_root: &Root,
+        trie_nodes: TrieNodes,
+    ) -> Result<()>;
+    // height of the imported snapshot and its Transactions
+    fn get_snapshot_base(&self) -> Result<Option<(u32, Vec<FinalizedTransaction>)>>;
+    // height of the snapshot base, without reading its Transactions
+    fn get_base_height(&self) -> Result<Option<u32>>;
+    // removes the Blocks above the snapshot base up to this height and keeps their headers.
+    // the Transactions they finalized first are appended to the snapshot base, trie roots
+    // below it are removed and the trie is replaced by the nodes reachable from the later roots
+    fn prune_blocks(&mut self, height: u32, trie_nodes: TrieNodes) -> Result<()>;
+    // every Transaction finalized up to this height at its first position, in chain order
+    fn get_finalized_transactions(&self, height: u32) -> Result<Vec<FinalizedTransaction>> {
+        let (base_height, mut finalized) = self.get_snapshot_base()?.unwrap_or((0, Vec::new()));
+        finalized.retain(|finalized| finalized.height <= height);
+        let mut seen: HashSet<GenericHash> = finalized
+            .iter()
+            .map(|finalized| finalized.transaction.hash())
+            .collect();
+        for block_height in (base_height + 1)..=height {
+            let block = self
+                .get_block_by_height(block_height)?
+                .ok_or_else(|| anyhow!("Block is missing: {}", block_height))?;
+            for (position, transaction) in block.transactions.into_iter().enumerate() {
+                if seen.insert(transaction.hash()) {
+                    finalized.push(FinalizedTransaction {
+                        height: block_height,
+                        position: position as u32,
+                        transaction,
+                    });
+                }
+            }
+        }
+        Ok(finalized)
+    }
+    fn latest_block(&self) -> Result<Block> {
+        let height = self.height()?;
+        self.get_block_by_height(height)?
+            .ok_or_else(|| anyhow!("Latest Block is missing: {}", height))
+    }
+    fn trie_root(&self) -> Result<Option<Root>> {
+        self.get_trie_root_by_height(self.height()?)
+    }
 }
-#[cfg(feature = "sqlite")]
-pub trait SqLiteBlockStore {
-    fn setup(&self);
-    fn trigger_genesis(&mut self, timestamp: Timestamp);
-    fn insert_block(&mut self, previous_height: u32, block: Block);
-    fn get_block_by_height(&self, height: u32) -> Block;
-    fn current_block_height(&self) -> u32;
+// a pending transaction with what admission control needs to know about it
+#[derive(Debug, Clone)]
+pub struct PoolEntry {
+    pub transaction: Transaction,
+    pub received_at: Timestamp,
+    // address of the client that scheduled the transaction
+    pub origin: Option<String>,
+    // part of the local proposal, the entry is only removed once
+    // a Block that contains it was finalized
+    pub reserved: bool,
 }
-#[cfg(feature = "sqlite")]
-pub struct BlockStore {
-    pub db_path: String,
+pub trait TransactionPool: Send + Sync {
+    fn insert_transaction(&mut self, entry: PoolEntry) -> Result<()>;
+    // entries in the order they were received
+    fn get_entries(&self) -> Result<Vec<PoolEntry>>;
+    fn get_all_transactions(&self) -> Result<Vec<Transaction>> {
+        Ok(self
+            .get_entries()?
+            .into_iter()
+            .map(|entry| entry.transaction)
+            .collect())
+    }
+    fn contains_transaction(&self, hash: &GenericHash) -> Result<bool>;
+    fn remove_transactions(&mut self, hashes: &[GenericHash]) -> Result<()>;
+    fn reserve_transactions(&mut self, hashes: &[GenericHash]) -> Result<()>;
+    // returns reserved entries to the pool when their proposal failed
+    fn release_reservations(&mut self) -> Result<()>;
+    fn usage(&self) -> &PoolUsage;
 }
-#[cfg(not(feature = "sqlite"))]
-pub struct BlockStore {
-    pub height: u32,
+#[derive(Default)]
+pub struct InMemoryBlockStore {
     pub blocks: HashMap<u32, Block>,
+    pub certificates: HashMap<u32, FinalityCertificate>,
+    pub evidence: Vec<EquivocationEvidence>,
+    pub transaction_locations: HashMap<GenericHash, (u32, u32)>,
+    pub trie_roots: HashMap<u32, Root>,
+    pub next_nonces: HashMap<GenericPublicKey, u64>,
+    pub snapshot_base: Option<(u32, Vec<FinalizedTransaction>)>,
+    // headers of pruned Blocks
+    pub headers: HashMap<u32, BlockHeader>,
+    pub last_signed: Option<LastSigned>,
+    pub trie_nodes: TrieNodes,
 }
-#[cfg(feature = "sqlite")]
-impl SqLiteBlockStore for BlockStore {
-    fn setup(&self) {
-        let conn = Connection::open(&self.db_path).unwrap();
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS blocks (
-            height BLOB PRIMARY KEY,
-            block BLOB NOT NULL
-            )",
-            [],
-        )
-        .unwrap();
+impl InMemoryBlockStore {
+    fn index_transaction(&mut self, transaction: &Transaction, height: u32, position: u32) {
+        self.transaction_locations
+            .entry(transaction.hash())
+            .or_insert((height, position));
+        if let Some(envelope) = &transaction.envelope {
+            let next_nonce = self.next_nonces.entry(envelope.sender.clone()).or_insert(0);
+            *next_nonce = (*next_nonce).max(envelope.nonce + 1);
+        }
     }
-    fn current_block_height(&self) -> u32 {
-        let conn = Connection::open(&self.db_path).unwrap();
-        let mut stmt = conn.prepare("SELECT COUNT(*) FROM blocks").unwrap();
-        let mut rows = stmt.query([]).unwrap();
-        let count: usize = rows.next().unwrap().unwrap().get(0).unwrap();
-        count as u32
+}
+impl BlockStore for InMemoryBlockStore {
+    fn commit_block(
+        &mut self,
+        block: Block,
+        certificate: Option<FinalityCertificate>,
+        trie_root: &Root,
+        trie_nodes: TrieNodes,
+    ) -> Result<()> {
+        for (position, transaction) in block.transactions.iter().enumerate() {
+            self.index_transaction(transaction, block.header.height, position as u32);
+        }
+        self.trie_nodes.extend(trie_nodes);
+        if let Some(certificate) = certificate {
+            self.certificates.insert(certificate.height, certificate);
+        }
+        self.trie_roots
+            .insert(block.header.height, trie_root.clone());
+        self.blocks.insert(block.header.height, block);
+        Ok(())
     }
-    fn get_block_by_height(&self, height: u32) -> Block {
-        let conn = Connection::open(&self.db_path).unwrap();
-        let mut stmt = conn
-            .prepare("SELECT block FROM blocks WHERE height = ?1 LIMIT 1")
-            .unwrap();
-        let block_serialized: Option<Vec<u8>> = stmt
-            .query_row([&height], |row| {
-                let block_serialized: Vec<u8> = row.get(0).unwrap();
-                Ok(Some(block_serialized))
+    fn get_block_by_height(&self, height: u32) -> Result<Option<Block>> {
+        Ok(self.blocks.get(&height).cloned())
+    }
+    fn get_header_by_height(&self, height: u32) -> Result<Option<BlockHeader>> {
+        Ok(match self.blocks.get(&height) {
+            Some(block) => Some(block.header.clone()),
+            None => self.headers.get(&height).cloned(),
+        })
+    }
+    fn height(&self) -> Result<u32> {
+        Ok(self.blocks.keys().max().cloned().unwrap_or(0))
+    }
+    fn get_certificate_by_height(&self, height: u32) -> Result<Option<FinalityCertificate>> {
+        Ok(self.certificates.get(&height).cloned())
+    }
+    fn get_trie_root_by_height(&self, height: u32) -> Result<Option<Root>> {
+        Ok(self.trie_roots.get(&height).cloned())
+    }
+    fn get_trie_node(&self, key: &[u8]) -> Result<Option<Node>> {
+        Ok(self.trie_nodes.get(key).cloned())
+    }
+    fn replace_trie_nodes(&mut self, trie_nodes: TrieNodes) -> Result<()> {
+        self.trie_nodes = trie_nodes;
+        Ok(())
+    }
+    fn insert_evidence(&mut self, evidence: EquivocationEvidence) -> Result<bool> {
+        if self.evidence.iter().any(|known| {
+            (known.height, known.round) == (evidence.height, evidence.round)
+                && known.validator == evidence.validator
+        }) {
+            return Ok(false);
+        }
+        self.evidence.push(evidence);
+        Ok(true)
+    }
+    fn get_evidence(&self) -> Result<Vec<EquivocationEvidence>> {
+        Ok(self.evidence.clone())
+    }
+    fn get_last_signed(&self) -> Result<Option<LastSigned>> {
+        Ok(self.last_signed.clone())
+    }
+    fn set_last_signed(&mut self, last_signed: LastSigned) -> Result<()> {
+        self.last_signed = Some(last_signed);
+        Ok(())
+    }
+    fn contains_transaction(&self, hash: &GenericHash) -> Result<bool> {
+        Ok(self.transaction_locations.contains_key(hash))
+    }
+    fn get_transaction_location(&self, hash: &GenericHash) -> Result<Option<(u32, u32)>> {
+        Ok(self.transaction_locations.get(hash).cloned())
+    }
+    fn get_sequenced_leaves(&self, height: u32) -> Result<Vec<SequencedLeaf>> {
+        let mut leaves: Vec<SequencedLeaf> = self
+            .transaction_locations
+            .iter()
+            .filter(|(_, (transaction_height, _))| *transaction_height <= height)
+            .map(|(hash, (height, position))| SequencedLeaf {
+                hash: hash.clone(),
+                height: *height,
+                position: *position,
             })
-            .unwrap_or(None);
-        // todo: don't expect this
-        bincode::deserialize(
-            &block_serialized.expect(&format!("[Error] Block not found: {}", &height)),
-        )
-        .unwrap()
+            .collect();
+        leaves.sort_by(|a, b| a.hash.cmp(&b.hash));
+        Ok(leaves)
     }
-    fn insert_block(&mut self, height: u32, block: Block) {
-        let conn = Connection::open(&self.db_path).unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO blocks (height, block) VALUES (?1, ?2)",
-            params![height, bincode::serialize(&block).unwrap()],
-        )
-        .unwrap();
+    fn get_next_nonce(&self, sender: &GenericPublicKey) -> Result<u64> {
+        Ok(self.next_nonces.get(sender).cloned().unwrap_or(0))
     }
-    fn trigger_genesis(&mut self, timestamp: Timestamp) {
-        self.insert_block(
-            0u32,
-            Block {
-                timestamp,
-                height: 0,
-                signature: Some(vec![]),
-                transactions: vec![],
-                commitments: None,
-            },
+    fn import_snapshot(
+        &mut self,
+        certified_block: CertifiedBlock,
+        transactions: &[FinalizedTransaction],
+        trie_root: &Root,
+        trie_nodes: TrieNodes,
+    ) -> Result<()> {
+        for finalized in transactions {
+            self.index_transaction(&finalized.transaction, finalized.height, finalized.position);
+        }
+        self.trie_nodes = HashMap::new();
+        let height = certified_block.block.header.height;
+        let replaced: Vec<u32> = self
+            .blocks
+            .keys()
+            .filter(|block_height| **block_height > 0 && **block_height < height)
+            .cloned()
+            .collect();
+        for block_height in replaced {
+            if let Some(block) = self.blocks.remove(&block_height) {
+                self.headers.insert(block_height, block.header);
+            }
+        }
+        self.trie_roots
+            .retain(|root_height, _| *root_height >= height);
+        self.trie_nodes = trie_nodes;
+        self.snapshot_base = Some((height, transactions.to_vec()));
+        self.commit_block(
+            certified_block.block,
+            Some(certified_block.certificate),
+            trie_root,
+            trie_nodes,
         )
     }
-}
-#[cfg(not(feature = "sqlite"))]
-impl InMemoryBlockStore for BlockStore {
-    fn empty() -> Self {
-        Self {
-            height: 1,
-            blocks: HashMap::new(),
-        }
-    }
-    fn trigger_genesis(&mut self, timestamp: Timestamp) {
-        self.blocks.insert(
-            0u32,
-            Block {
-                timestamp,
-                height: 0,
-                signature: Some(vec![]),
-                transactions: vec![],
-                commitments: None,
-            },
-        );
+    fn get_snapshot_base(&self) -> Result<Option<(u32, Vec<FinalizedTransaction>)>> {
+        Ok(self.snapshot_base.clone())
+    }
+    fn get_base_height(&self) -> Result<Option<u32>> {
+        Ok(self.snapshot_base.as_ref().map(|(height, _)| *height))
     }
-    fn insert_block(&mut self, previous_height: u32, block: Block) {
-        self.blocks.insert(previous_height + 1, block);
-        self.height = self.blocks.len() as u32;
+    fn prune_blocks(&mut self, height: u32, trie_nodes: TrieNodes) -> Result<()> {
+        let (base_height, mut transactions) = self.snapshot_base.take().unwrap_or_default();
+        for block_height in (base_height + 1)..=height {
+            if let Some(block) = self.blocks.remove(&block_height) {
+                for (position, transaction) in block.transactions.into_iter().enumerate() {
+                    let location = (block_height, position as u32);
+                    if self.transaction_locations.get(&transaction.hash()) == Some(&location) {
+                        transactions.push(FinalizedTransaction {
+                            height: block_height,
+                            position: position as u32,
+                            transaction,
+                        });
+                    }
+                }
+                self.headers.insert(block_height, block.header);
+            }
+        }
+        self.trie_roots
+            .retain(|root_height, _| *root_height >= height);
+        self.snapshot_base = Some((height.max(base_height), transactions));
+        Ok(())
     }
-    fn get_block_by_height(&self, height: u32) -> Block {
-        self.blocks
-            .get(&height)
-            .expect("Failed to get Block")
-            .clone()
+}
+// the block store and the pool share one long-lived connection
+#[cfg(feature = "sqlite")]
+pub type SharedConnection = Arc<Mutex<Connection>>;
+#[cfg(feature = "sqlite")]
+pub fn open_database(db_path: &str) -> Result<SharedConnection> {
+    let conn = Connection::open(db_path)?;
+    // readers don't block the writer and commits survive crashes
+    let journal_mode: String =
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+    if journal_mode.to_lowercase() != "wal" {
+        bail!("Failed to enable WAL mode: {}", journal_mode);
     }
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS blocks (
+            height BLOB PRIMARY KEY,
+            block BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS headers (
+            height INTEGER PRIMARY KEY,
+            header BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS certificates (
+            height BLOB PRIMARY KEY,
+            certificate BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS transactions (
+            hash BLOB PRIMARY KEY,
+            height INTEGER NOT NULL,
+            position INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS evidence (
+            height BLOB NOT NULL,
+            round INTEGER NOT NULL,
+            validator BLOB NOT NULL,
+            evidence BLOB NOT NULL,
+            PRIMARY KEY (height, round, validator)
+        );
+        CREATE TABLE IF NOT EXISTS last_signed (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            last_signed BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS trie_roots (
+            height INTEGER PRIMARY KEY,
+            root BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS trie_nodes (
+            key BLOB PRIMARY KEY,
+            node BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS nonces (
+            sender BLOB PRIMARY KEY,
+            next_nonce INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS snapshot_base (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            height INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS snapshot_transactions (
+            height INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            tx BLOB NOT NULL,
+            PRIMARY KEY (height, position)
+        );
+        CREATE TABLE IF NOT EXISTS pool (
+            hash BLOB PRIMARY KEY,
+            tx BLOB NOT NULL,
+            received_at INTEGER NOT NULL,
+            origin TEXT,
+            reserved INTEGER NOT NULL DEFAULT 0
+        );",
+    )?;
+    Ok(Arc::new(Mutex::new(conn)))
 }
-#[cfg(not(feature = "sqlite"))]
-pub trait InMemoryTransactionPool {
-    fn empty() -> Self;
-    fn insert_transaction(&mut self, transaction: Transaction);
-    #[allow(unused)]
-    fn get_transaction_by_index(&self, index: u32) -> &Transaction;
-    fn reinitialize(&mut self);
+#[cfg(feature = "sqlite")]
+fn lock_connection(conn: &SharedConnection) -> Result<MutexGuard<'_, Connection>> {
+    conn.lock()
+        .map_err(|_| anyhow!("Database connection is poisoned"))
 }
-
-// note: can be used for other dbs and should therefore be renamed
+// stores the Block with its certificate and trie root and indexes its transactions,
+// part of the caller's database transaction
 #[cfg(feature = "sqlite")]
-pub trait SqLiteTransactionPool {
-    fn setup(&self);
-    fn insert_transaction(&mut self, transaction: Transaction);
-    fn get_transaction_by_index(&self, index: u32) -> Transaction;
-    fn get_all_transactions(&self) -> Vec<Transaction>;
-    fn reinitialize(&mut self);
+fn write_block(
+    tx: &rusqlite::Transaction,
+    block: Block,
+    certificate: Option<FinalityCertificate>,
+    trie_root: &Root,
+) -> Result<()> {
+    tx.prepare_cached("INSERT OR REPLACE INTO blocks (height, block) VALUES (?1, ?2)")?
+        .execute(params![block.header.height, bincode::serialize(&block)?])?;
+    let mut index_transaction = tx.prepare_cached(
+        "INSERT OR IGNORE INTO transactions (hash, height, position) VALUES (?1, ?2, ?3)",
+    )?;
+    let mut remove_from_pool = tx.prepare_cached("DELETE FROM pool WHERE hash = ?1")?;
+    let mut advance_nonce = tx.prepare_cached(
+        "INSERT INTO nonces (sender, next_nonce) VALUES (?1, ?2)
+        ON CONFLICT(sender) DO UPDATE SET next_nonce = MAX(next_nonce, excluded.next_nonce)",
+    )?;
+    for (position, transaction) in block.transactions.iter().enumerate() {
+        let transaction_hash = transaction.hash();
+        index_transaction.execute(params![
+            transaction_hash,
+            block.header.height,
+            position as u32
+        ])?;
+        remove_from_pool.execute([&transaction_hash])?;
+        if let Some(envelope) = &transaction.envelope {
+            advance_nonce.execute(params![envelope.sender, envelope.nonce + 1])?;
+        }
+    }
+    if let Some(certificate) = certificate {
+        tx.prepare_cached(
+            "INSERT OR REPLACE INTO certificates (height, certificate) VALUES (?1, ?2)",
+        )?
+        .execute(params![
+            certificate.height,
+            bincode::serialize(&certificate)?
+        ])?;
+    }
+    tx.prepare_cached("INSERT OR REPLACE INTO trie_roots (height, root) VALUES (?1, ?2)")?
+        .execute(params![block.header.height, bincode::serialize(trie_root)?])?;
+    Ok(())
 }
-#[cfg(not(feature = "sqlite"))]
-pub struct TransactionPool {
-    pub size: u32,
-    pub transactions: HashMap<u32, Transaction>,
+// trie nodes are content addressed, a node that is already stored is the same node.
+// part of the caller's database transaction
+#[cfg(feature = "sqlite")]
+fn write_trie_nodes(tx: &rusqlite::Transaction, trie_nodes: &TrieNodes) -> Result<()> {
+    let mut insert_node =
+        tx.prepare_cached("INSERT OR IGNORE INTO trie_nodes (key, node) VALUES (?1, ?2)")?;
+    for (key, node) in trie_nodes {
+        insert_node.execute(params![key, bincode::serialize(node)?])?;
+    }
+    Ok(())
 }
+// appends the Transactions to the snapshot base and moves it to the height,
+// part of the caller's database transaction
 #[cfg(feature = "sqlite")]
-pub struct TransactionPool {
-    pub size: u32,
-    pub db_path: String,
+fn write_snapshot_base(
+    tx: &rusqlite::Transaction,
+    height: u32,
+    transactions: &[FinalizedTransaction],
+) -> Result<()> {
+    let mut insert_snapshot_transaction = tx.prepare_cached(
+        "INSERT OR IGNORE INTO snapshot_transactions (height, position, tx) VALUES (?1, ?2, ?3)",
+    )?;
+    for finalized in transactions {
+        insert_snapshot_transaction.execute(params![
+            finalized.height,
+            finalized.position,
+            bincode::serialize(&finalized.transaction)?
+        ])?;
+    }
+    tx.prepare_cached("INSERT OR REPLACE INTO snapshot_base (id, height) VALUES (0, ?1)")?
+        .execute([height])?;
+    Ok(())
 }
 #[cfg(feature = "sqlite")]
-impl SqLiteTransactionPool for TransactionPool {
-    fn setup(&self) {
-        let conn = Connection::open(&self.db_path).unwrap();
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS txns (
-                uid BLOB PRIMARY KEY,
-                tx BLOB NOT NULL
-            )",
-            [],
-        )
-        .unwrap();
+pub struct SqLiteBlockStore {
+    pub conn: SharedConnection,
+}
+#[cfg(feature = "sqlite")]
+impl BlockStore for SqLiteBlockStore {
+    fn commit_block(
+        &mut self,
+        block: Block,
+        certificate: Option<FinalityCertificate>,
+        trie_root: &Root,
+        trie_nodes: TrieNodes,
+    ) -> Result<()> {
+        let mut conn = lock_connection(&self.conn)?;
+        let tx = conn.transaction()?;
+        write_trie_nodes(&tx, &trie_nodes)?;
+        write_block(&tx, block, certificate, trie_root)?;
+        tx.commit()?;
+        Ok(())
     }
-    fn get_transaction_by_index(&self, index: u32) -> Transaction {
-        let conn = Connection::open(&self.db_path).unwrap();
-        let mut stmt = conn
-            .prepare("SELECT tx FROM txns WHERE uid = ?1 LIMIT 1")
-            .unwrap();
-
-        let transaction_serialized: Option<Vec<u8>> = stmt
-            .query_row([&index], |row| {
-                let node_serialized: Vec<u8> = row.get(0).unwrap();
-                Ok(Some(node_serialized))
-            })
-            .unwrap_or(None);
-
-        bincode::deserialize(&transaction_serialized.expect("[Error] Block not found")).unwrap()
-    }
-    fn get_all_transactions(&self) -> Vec<Transaction> {
-        let conn = Connection::open(&self.db_path).unwrap();
-        let mut stmt = conn.prepare("SELECT tx FROM txns").unwrap();
-        let transaction_iter = stmt
-            .query_map([], |row| {
-                let transaction_blob: Vec<u8> = row.get(0)?;
-                // Deserialize the BLOB back into a Transaction
-                let transaction: Transaction = bincode::deserialize(&transaction_blob).unwrap();
-                Ok(transaction)
+    fn get_block_by_height(&self, height: u32) -> Result<Option<Block>> {
+        let conn = lock_connection(&self.conn)?;
+        let block_serialized: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT block FROM blocks WHERE height = ?1 LIMIT 1")?
+            .query_row([&height], |row| row.get(0))
+            .optional()?;
+        match block_serialized {
+            Some(block_serialized) => Ok(Some(bincode::deserialize(&block_serialized)?)),
+            None => Ok(None),
+        }
+    }
+    fn get_header_by_height(&self, height: u32) -> Result<Option<BlockHeader>> {
+        if let Some(block) = self.get_block_by_height(height)? {
+            return Ok(Some(block.header));
+        }
+        let conn = lock_connection(&self.conn)?;
+        let header_serialized: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT header FROM headers WHERE height = ?1")?
+            .query_row([&height], |row| row.get(0))
+            .optional()?;
+        match header_serialized {
+            Some(header_serialized) => Ok(Some(bincode::deserialize(&header_serialized)?)),
+            None => Ok(None),
+        }
+    }
+    fn height(&self) -> Result<u32> {
+        let conn = lock_connection(&self.conn)?;
+        let height: Option<u32> = conn
+            .prepare_cached("SELECT MAX(height) FROM blocks")?
+            .query_row([], |row| row.get(0))?;
+        Ok(height.unwrap_or(0))
+    }
+    fn get_certificate_by_height(&self, height: u32) -> Result<Option<FinalityCertificate>> {
+        let conn = lock_connection(&self.conn)?;
+        let certificate_serialized: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT certificate FROM certificates WHERE height = ?1 LIMIT 1")?
+            .query_row([&height], |row| row.get(0))
+            .optional()?;
+        match certificate_serialized {
+            Some(certificate_serialized) => {
+                Ok(Some(bincode::deserialize(&certificate_serialized)?))
+            }
+            None => Ok(None),
+        }
+    }
+    fn get_trie_root_by_height(&self, height: u32) -> Result<Option<Root>> {
+        let conn = lock_connection(&self.conn)?;
+        let trie_root_serialized: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT root FROM trie_roots WHERE height = ?1")?
+            .query_row([&height], |row| row.get(0))
+            .optional()?;
+        match trie_root_serialized {
+            Some(trie_root_serialized) => Ok(Some(bincode::deserialize(&trie_root_serialized)?)),
+            None => Ok(None),
+        }
+    }
+    fn get_trie_node(&self, key: &[u8]) -> Result<Option<Node>> {
+        let conn = lock_connection(&self.conn)?;
+        let node_serialized: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT node FROM trie_nodes WHERE key = ?1")?
+            .query_row([key], |row| row.get(0))
+            .optional()?;
+        match node_serialized {
+            Some(node_serialized) => Ok(Some(bincode::deserialize(&node_serialized)?)),
+            None => Ok(None),
+        }
+    }
+    fn replace_trie_nodes(&mut self, trie_nodes: TrieNodes) -> Result<()> {
+        let mut conn = lock_connection(&self.conn)?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM trie_nodes", [])?;
+        write_trie_nodes(&tx, &trie_nodes)?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn insert_evidence(&mut self, evidence: EquivocationEvidence) -> Result<bool> {
+        let conn = lock_connection(&self.conn)?;
+        let inserted = conn
+            .prepare_cached(
+                "INSERT OR IGNORE INTO evidence (height, round, validator, evidence) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(params![
+                evidence.height,
+                evidence.round,
+                evidence.validator,
+                bincode::serialize(&evidence)?
+            ])?;
+        Ok(inserted > 0)
+    }
+    fn get_evidence(&self) -> Result<Vec<EquivocationEvidence>> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt =
+            conn.prepare_cached("SELECT evidence FROM evidence ORDER BY height, round")?;
+        let evidence_blobs = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut evidence = Vec::new();
+        for evidence_blob in evidence_blobs {
+            evidence.push(bincode::deserialize(&evidence_blob?)?);
+        }
+        Ok(evidence)
+    }
+    fn get_last_signed(&self) -> Result<Option<LastSigned>> {
+        let conn = lock_connection(&self.conn)?;
+        let last_signed_serialized: Option<Vec<u8>> = conn
+            .prepare_cached("SELECT last_signed FROM last_signed WHERE id = 0")?
+            .query_row([], |row| row.get(0))
+            .optional()?;
+        match last_signed_serialized {
+            Some(last_signed_serialized) => {
+                Ok(Some(bincode::deserialize(&last_signed_serialized)?))
+            }
+            None => Ok(None),
+        }
+    }
+    fn set_last_signed(&mut self, last_signed: LastSigned) -> Result<()> {
+        let conn = lock_connection(&self.conn)?;
+        conn.prepare_cached("INSERT OR REPLACE INTO last_signed (id, last_signed) VALUES (0, ?1)")?
+            .execute([bincode::serialize(&last_signed)?])?;
+        Ok(())
+    }
+    fn contains_transaction(&self, hash: &GenericHash) -> Result<bool> {
+        let conn = lock_connection(&self.conn)?;
+        let count: usize = conn
+            .prepare_cached("SELECT COUNT(*) FROM transactions WHERE hash = ?1")?
+            .query_row([hash], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+    fn get_transaction_location(&self, hash: &GenericHash) -> Result<Option<(u32, u32)>> {
+        let conn = lock_connection(&self.conn)?;
+        let location = conn
+            .prepare_cached("SELECT height, position FROM transactions WHERE hash = ?1")?
+            .query_row([hash], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+        Ok(location)
+    }
+    fn get_sequenced_leaves(&self, height: u32) -> Result<Vec<SequencedLeaf>> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn.prepare_cached(
+            "SELECT hash, height, position FROM transactions WHERE height <= ?1 ORDER BY hash",
+        )?;
+        let leaf_rows = stmt.query_map([&height], |row| {
+            Ok(SequencedLeaf {
+                hash: row.get(0)?,
+                height: row.get(1)?,
+                position: row.get(2)?,
             })
-            .unwrap();
+        })?;
+        let mut leaves = Vec::new();
+        for leaf in leaf_rows {
+            leaves.push(leaf?);
+        }
+        Ok(leaves)
+    }
+    fn get_next_nonce(&self, sender: &GenericPublicKey) -> Result<u64> {
+        let conn = lock_connection(&self.conn)?;
+        let next_nonce: Option<u64> = conn
+            .prepare_cached("SELECT next_nonce FROM nonces WHERE sender = ?1")?
+            .query_row([sender], |row| row.get(0))
+            .optional()?;
+        Ok(next_nonce.unwrap_or(0))
+    }
+    fn import_snapshot(
+        &mut self,
+        certified_block: CertifiedBlock,
+        transactions: &[FinalizedTransaction],
+        trie_root: &Root,
+        trie_nodes: TrieNodes,
+    ) -> Result<()> {
+        let mut conn = lock_connection(&self.conn)?;
+        let tx = conn.transaction()?;
+        {
+            let mut index_transaction = tx.prepare_cached(
+                "INSERT OR IGNORE INTO transactions (hash, height, position) VALUES (?1, ?2, ?3)",
+            )?;
+            let mut advance_nonce = tx.prepare_cached(
+                "INSERT INTO nonces (sender, next_nonce) VALUES (?1, ?2)
+                ON CONFLICT(sender) DO UPDATE SET next_nonce = MAX(next_nonce, excluded.next_nonce)",
+            )?;
+            for finalized in transactions {
+                index_transaction.execute(params![
+                    finalized.transaction.hash(),
+                    finalized.height,
+                    finalized.position
+                ])?;
+                if let Some(envelope) = &finalized.transaction.envelope {
+                    advance_nonce.execute(params![envelope.sender, envelope.nonce + 1])?;
+                }
+            }
+        }
+        let height = certified_block.block.header.height;
+        {
+            let mut replaced_blocks =
+                tx.prepare_cached("SELECT block FROM blocks WHERE height BETWEEN 1 AND ?1")?;
+            let mut insert_header = tx.prepare_cached(
+                "INSERT OR REPLACE INTO headers (height, header) VALUES (?1, ?2)",
+            )?;
+            let block_blobs = replaced_blocks
+                .query_map([height.saturating_sub(1)], |row| row.get::<_, Vec<u8>>(0))?;
+            for block_blob in block_blobs {
+                let block: Block = bincode::deserialize(&block_blob?)?;
+                insert_header.execute(params![
+                    block.header.height,
+                    bincode::serialize(&block.header)?
+                ])?;
+            }
+        }
+        tx.prepare_cached("DELETE FROM blocks WHERE height BETWEEN 1 AND ?1")?
+            .execute([height.saturating_sub(1)])?;
+        tx.prepare_cached("DELETE FROM trie_roots WHERE height < ?1")?
+            .execute([height])?;
+        tx.execute("DELETE FROM trie_nodes", [])?;
+        write_trie_nodes(&tx, &trie_nodes)?;
+        write_snapshot_base(&tx, height, transactions)?;
+        write_block(
+            &tx,
+            certified_block.block,
+            Some(certified_block.certificate),
+            trie_root,
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+    fn get_snapshot_base(&self) -> Result<Option<(u32, Vec<FinalizedTransaction>)>> {
+        let conn = lock_connection(&self.conn)?;
+        let height: Option<u32> = conn
+            .prepare_cached("SELECT height FROM snapshot_base WHERE id = 0")?
+            .query_row([], |row| row.get(0))
+            .optional()?;
+        let height = match height {
+            Some(height) => height,
+            None => return Ok(None),
+        };
+        let mut stmt = conn.prepare_cached(
+            "SELECT height, position, tx FROM snapshot_transactions ORDER BY height, position",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, Vec<u8>>(2)?))
+        })?;
         let mut transactions = Vec::new();
-        for transaction in transaction_iter {
-            transactions.push(transaction.unwrap());
+        for row in rows {
+            let (height, position, transaction_serialized) = row?;
+            transactions.push(FinalizedTransaction {
+                height,
+                position,
+                transaction: bincode::deserialize(&transaction_serialized)?,
+            });
         }
-        transactions
+        Ok(Some((height, transactions)))
     }
-    fn insert_transaction(&mut self, transaction: Transaction) {
-        let conn = Connection::open(&self.db_path).unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO txns (tx) VALUES (?1)",
-            params![bincode::serialize(&transaction).unwrap()],
-        )
-        .unwrap();
-        // todo: read size from db
-        self.size += 1;
+    fn get_base_height(&self) -> Result<Option<u32>> {
+        let conn = lock_connection(&self.conn)?;
+        let height = conn
+            .prepare_cached("SELECT height FROM snapshot_base WHERE id = 0")?
+            .query_row([], |row| row.get(0))
+            .optional()?;
+        Ok(height)
     }
-    fn reinitialize(&mut self) {
-        // todo: remove when reading size from db
-        self.size = 0;
-        let conn = Connection::open(&self.db_path).unwrap();
-        conn.execute("DROP TABLE IF EXISTS txns", []).unwrap();
-        self.setup();
+    fn prune_blocks(&mut self, height: u32, trie_nodes: TrieNodes) -> Result<()> {
+        let mut conn = lock_connection(&self.conn)?;
+        let tx = conn.transaction()?;
+        // only the Blocks above the base are left, earlier ones were pruned before
+        let mut pruned_transactions = Vec::new();
+        {
+            let mut pruned_blocks =
+                tx.prepare_cached("SELECT block FROM blocks WHERE height BETWEEN 1 AND ?1")?;
+            let mut insert_header = tx.prepare_cached(
+                "INSERT OR REPLACE INTO headers (height, header) VALUES (?1, ?2)",
+            )?;
+            let mut first_location =
+                tx.prepare_cached("SELECT height, position FROM transactions WHERE hash = ?1")?;
+            let block_blobs = pruned_blocks.query_map([&height], |row| row.get::<_, Vec<u8>>(0))?;
+            for block_blob in block_blobs {
+                let block: Block = bincode::deserialize(&block_blob?)?;
+                for (position, transaction) in block.transactions.into_iter().enumerate() {
+                    let location: Option<(u32, u32)> = first_location
+                        .query_row([transaction.hash()], |row| Ok((row.get(0)?, row.get(1)?)))
+                        .optional()?;
+                    if location == Some((block.header.height, position as u32)) {
+                        pruned_transactions.push(FinalizedTransaction {
+                            height: block.header.height,
+                            position: position as u32,
+                            transaction,
+                        });
+                    }
+                }
+                insert_header.execute(params![
+                    block.header.height,
+                    bincode::serialize(&block.header)?
+                ])?;
+            }
+        }
+        tx.prepare_cached("DELETE FROM blocks WHERE height BETWEEN 1 AND ?1")?
+            .execute([&height])?;
+        tx.prepare_cached("DELETE FROM trie_roots WHERE height < ?1")?
+            .execute([&height])?;
+        tx.execute("DELETE FROM trie_nodes", [])?;
+        write_trie_nodes(&tx, &trie_nodes)?;
+        write_snapshot_base(&tx, height, &pruned_transactions)?;
+        tx.commit()?;
+        Ok(())
     }
 }
-
-#[cfg(not(feature = "sqlite"))]
-impl InMemoryTransactionPool for TransactionPool {
-    fn empty() -> Self {
-        Self {
-            size: 0,
-            transactions: HashMap::new(),
+#[derive(Default)]
+pub struct InMemoryTransactionPool {
+    pub entries: Vec<PoolEntry>,
+    pub usage: PoolUsage,
+}
+impl TransactionPool for InMemoryTransactionPool {
+    fn insert_transaction(&mut self, entry: PoolEntry) -> Result<()> {
+        self.usage.add(&entry);
+        self.entries.push(entry);
+        Ok(())
+    }
+    fn get_entries(&self) -> Result<Vec<PoolEntry>> {
+        Ok(self.entries.clone())
+    }
+    fn contains_transaction(&self, hash: &GenericHash) -> Result<bool> {
+        Ok(self
+            .entries
+            .iter()
+            .any(|entry| &entry.transaction.hash() == hash))
+    }
+    fn remove_transactions(&mut self, hashes: &[GenericHash]) -> Result<()> {
+        self.entries
+            .retain(|entry| !hashes.contains(&entry.transaction.hash()));
+        for hash in hashes {
+            self.usage.remove(hash);
         }
+        Ok(())
     }
-    fn insert_transaction(&mut self, transaction: Transaction) {
-        self.transactions.insert(self.size, transaction);
-        self.size += 1;
+    fn reserve_transactions(&mut self, hashes: &[GenericHash]) -> Result<()> {
+        for entry in &mut self.entries {
+            if hashes.contains(&entry.transaction.hash()) {
+                entry.reserved = true;
+            }
+        }
+        Ok(())
     }
-    fn get_transaction_by_index(&self, index: u32) -> &Transaction {
-        self.transactions
-            .get(&index)
-            .expect("Failed to get Transaction")
+    fn release_reservations(&mut self) -> Result<()> {
+        for entry in &mut self.entries {
+            entry.reserved = false;
+        }
+        Ok(())
     }
-    fn reinitialize(&mut self) {
-        self.size = 0;
-        self.transactions = HashMap::new();
+    fn usage(&self) -> &PoolUsage {
+        &self.usage
+    }
+}
+#[cfg(feature = "sqlite")]
+pub struct SqLiteTransactionPool {
+    pub conn: SharedConnection,
+    pub usage: PoolUsage,
+}
+#[cfg(feature = "sqlite")]
+impl SqLiteTransactionPool {
+    // the usage is counted once from the stored entries
+    pub fn open(conn: SharedConnection) -> Result<Self> {
+        let mut pool = Self {
+            conn,
+            usage: PoolUsage::default(),
+        };
+        for entry in pool.get_entries()? {
+            pool.usage.add(&entry);
+        }
+        Ok(pool)
+    }
+}
+#[cfg(feature = "sqlite")]
+impl TransactionPool for SqLiteTransactionPool {
+    fn insert_transaction(&mut self, entry: PoolEntry) -> Result<()> {
+        let conn = lock_connection(&self.conn)?;
+        conn.prepare_cached(
+            "INSERT INTO pool (hash, tx, received_at, origin, reserved) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?
+        .execute(params![
+            entry.transaction.hash(),
+            bincode::serialize(&entry.transaction)?,
+            entry.received_at,
+            entry.origin,
+            entry.reserved
+        ])?;
+        self.usage.add(&entry);
+        Ok(())
+    }
+    fn get_entries(&self) -> Result<Vec<PoolEntry>> {
+        let conn = lock_connection(&self.conn)?;
+        let mut stmt = conn
+            .prepare_cached("SELECT tx, received_at, origin, reserved FROM pool ORDER BY rowid")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, Timestamp>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, bool>(3)?,
+            ))
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let (transaction_blob, received_at, origin, reserved) = row?;
+            entries.push(PoolEntry {
+                transaction: bincode::deserialize(&transaction_blob)?,
+                received_at,
+                origin,
+                reserved,
+            });
+        }
+        Ok(entries)
+    }
+    fn contains_transaction(&self, hash: &GenericHash) -> Result<bool> {
+        let conn = lock_connection(&self.conn)?;
+        let count: usize = conn
+            .prepare_cached("SELECT COUNT(*) FROM pool WHERE hash = ?1")?
+            .query_row([hash], |row| row.get(0))?;
+        Ok(count > 0)
+    }
+    fn remove_transactions(&mut self, hashes: &[GenericHash]) -> Result<()> {
+        let mut conn = lock_connection(&self.conn)?;
+        let tx = conn.transaction()?;
+        {
+            let mut remove_from_pool = tx.prepare_cached("DELETE FROM pool WHERE hash = ?1")?;
+            for hash in hashes {
+                remove_from_pool.execute([hash])?;
+            }
+        }
+        tx.commit()?;
+        for hash in hashes {
+            self.usage.remove(hash);
+        }
+        Ok(())
+    }
+    fn reserve_transactions(&mut self, hashes: &[GenericHash]) -> Result<()> {
+        let mut conn = lock_connection(&self.conn)?;
+        let tx = conn.transaction()?;
+        {
+            let mut reserve = tx.prepare_cached("UPDATE pool SET reserved = 1 WHERE hash = ?1")?;
+            for hash in hashes {
+                reserve.execute([hash])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+    fn release_reservations(&mut self) -> Result<()> {
+        let conn = lock_connection(&self.conn)?;
+        conn.prepare_cached("UPDATE pool SET reserved = 0")?
+            .execute([])?;
+        Ok(())
+    }
+    fn usage(&self) -> &PoolUsage {
+        &self.usage
     }
 }
 pub struct InMemoryConsensus {
     pub validators: Vec<VerifyingKey>,
     pub local_validator: VerifyingKey,
     pub local_signing_key: SigningKey,
+    pub consensus_threshold: Option<u32>,
+    pub round_duration: u32,
+    pub clearing_phase_duration: u32,
     pub commitments: Vec<Vec<ConsensusCommitment>>,
     pub round_winner: Option<VerifyingKey>,
     pub proposed: bool,
     pub committed: bool,
     pub signed: bool,
     pub lowest_block: Option<Vec<u8>>,
+    // the lowest valid proposal at this height, its transactions are included but not finalized
+    pub pending_block: Option<Block>,
+    // view change state for the current height
+    pub skipped_rounds: u32,
+    pub last_view_change: Timestamp,
+    pub failed_leaders: Vec<VerifyingKey>,
+    // timeout votes by height and round, only votes for the current view are kept
+    pub timeout_votes: HashMap<(u32, u32), Vec<TimeoutVote>>,
+    pub view: (u32, u32),
+    pub timed_out: bool,
+    // the first header each validator signed at a height and round
+    pub signed_headers: HashMap<(u32, u32, GenericPublicKey), SignedHeader>,
+    // the validator set in effect from each height on, and changes that
+    // were finalized but are not active yet, keyed by activation height
+    pub validator_sets: BTreeMap<u32, ValidatorSet>,
+    pub pending_changes: BTreeMap<u32, Vec<ValidatorSetChange>>,
 }
 impl InMemoryConsensus {
-    #[allow(unused)]
-    pub fn empty() -> Self {
+    pub fn from_genesis(genesis: &Genesis, local_signing_key: SigningKey) -> Self {
         Self {
-            validators: Vec::new(),
-            local_validator: v1_vk_deserialized(),
-            local_signing_key: v2_sk_deserialized(),
-            commitments: Vec::new(),
-            round_winner: None,
-            proposed: false,
-            committed: false,
-            signed: false,
-            lowest_block: None,
-        }
-    }
-    pub fn empty_with_default_validators() -> InMemoryConsensus {
-        use crate::config::consensus::v2_vk_deserialized;
-        let local_validator_test_id = env::var("LOCAL_VALIDATOR").unwrap_or(0.to_string());
-        let local_validator = if local_validator_test_id == "0" {
-            (v1_sk_deserialized(), v1_vk_deserialized())
-        } else if local_validator_test_id == "1" {
-            (v2_sk_deserialized(), v2_vk_deserialized())
-        } else if local_validator_test_id == "2" {
-            (v3_sk_deserialized(), v3_vk_deserialized())
-        } else {
-            (v4_sk_deserialized(), v4_vk_deserialized())
-        };
-        Self {
-            validators: vec![
-                v1_vk_deserialized(),
-                v2_vk_deserialized(),
-                v3_vk_deserialized(),
-                v4_vk_deserialized(),
-            ],
-            local_validator: local_validator.1,
-            local_signing_key: local_validator.0,
+            validators: genesis.validators.clone(),
+            local_validator: VerifyingKey::from(&local_signing_key),
+            local_signing_key,
+            consensus_threshold: genesis.consensus_threshold,
+            round_duration: genesis.round_duration,
+            clearing_phase_duration: genesis.clearing_phase_duration,
             commitments: Vec::new(),
             round_winner: None,
             proposed: false,
             committed: false,
             signed: false,
             lowest_block: None,
+            pending_block: None,
+            skipped_rounds: 0,
+            last_view_change: 0,
+            failed_leaders: Vec::new(),
+            timeout_votes: HashMap::new(),
+            view: (0, 0),
+            timed_out: false,
+            signed_headers: HashMap::new(),
+            validator_sets: BTreeMap::from([(
+                0,
+                ValidatorSet {
+                    validators: genesis
+                        .validators
+                        .iter()
+                        .map(|validator| validator.to_sec1_bytes().to_vec())
+                        .collect(),
+                    consensus_threshold: genesis.consensus_threshold,
+                },
+            )]),
+            pending_changes: BTreeMap::new(),
         }
     }
     pub fn reinitialize(&mut self) {
@@ -300,5 +1022,418 @@ impl InMemoryConsensus {
         self.committed = false;
         self.signed = false;
         self.lowest_block = None;
+        self.pending_block = None;
+        self.timeout_votes = HashMap::new();
+        self.timed_out = false;
+    }
+    // number of distinct validator signatures required for finality
+    pub fn quorum(&self) -> u32 {
+        match self.consensus_threshold {
+            Some(consensus_threshold) => consensus_threshold,
+            None => bft_quorum(self.validators.len()),
+        }
+    }
+    // rounds are derived from the time since the last Block,
+    // every view change skips one round ahead
+    pub fn round(&self, last_block_unix_timestamp: u32) -> u32 {
+        current_round(last_block_unix_timestamp, self.round_duration) + self.skipped_rounds
+    }
+    // validators that failed to lead at the current height are excluded
+    pub fn active_validators(&self) -> Vec<VerifyingKey> {
+        let active_validators: Vec<VerifyingKey> = self
+            .validators
+            .iter()
+            .filter(|validator| !self.failed_leaders.contains(validator))
+            .cloned()
+            .collect();
+        if active_validators.is_empty() {
+            return self.validators.clone();
+        }
+        active_validators
+    }
+    pub fn committing_validator(&self, last_block_unix_timestamp: u32) -> VerifyingKey {
+        get_committing_validator(
+            self.round(last_block_unix_timestamp),
+            self.active_validators(),
+        )
+    }
+    // votes of earlier rounds are dropped and the local validator may vote again
+    // whenever the height or round changes
+    pub fn enter_view(&mut self, height: u32, round: u32) {
+        if self.view != (height, round) {
+            self.view = (height, round);
+            self.timeout_votes = HashMap::new();
+            self.timed_out = false;
+        }
+    }
+    // returns a certificate for the current view once the votes reach the threshold,
+    // votes for any other height or round are dropped
+    pub fn add_timeout_vote(&mut self, vote: TimeoutVote) -> Option<TimeoutCertificate> {
+        let (height, round) = self.view;
+        if (vote.height, vote.round) != self.view {
+            return None;
+        }
+        let quorum = self.quorum();
+        let votes = self.timeout_votes.entry(self.view).or_default();
+        if votes
+            .iter()
+            .any(|timeout_vote| timeout_vote.validator == vote.validator)
+        {
+            return None;
+        }
+        let chain_id = vote.chain_id.clone();
+        votes.push(vote);
+        if votes.len() as u32 == quorum {
+            return Some(TimeoutCertificate {
+                chain_id,
+                height,
+                round,
+                votes: votes.clone(),
+            });
+        }
+        None
+    }
+    // excludes the committing validator of the failed round
+    // and moves on to the next round immediately
+    pub fn apply_view_change(&mut self, last_block_unix_timestamp: u32) {
+        let failed_leader = self.committing_validator(last_block_unix_timestamp);
+        self.failed_leaders.push(failed_leader);
+        self.skipped_rounds += 1;
+        self.last_view_change = get_current_time();
+        self.reinitialize();
+    }
+    // the validator set that signs the Block at this height
+    pub fn validator_set_at(&self, height: u32) -> ValidatorSet {
+        self.validator_sets
+            .range(..=height)
+            .next_back()
+            .map(|(_, validator_set)| validator_set.clone())
+            .unwrap()
+    }
+    // must be called for every finalized Block in order, governance transactions
+    // are scheduled and changes that activate at the next height are applied
+    pub fn apply_governance(&mut self, block: &Block) {
+        for transaction in &block.transactions {
+            if let Some(governance) = &transaction.governance {
+                self.pending_changes
+                    .entry(governance.proposal.activation_height)
+                    .or_default()
+                    .push(governance.proposal.change.clone());
+            }
+        }
+        let next_height = block.header.height + 1;
+        let changes = match self.pending_changes.remove(&next_height) {
+            Some(changes) => changes,
+            None => return,
+        };
+        for change in changes {
+            match change {
+                ValidatorSetChange::AddValidator(validator) => {
+                    // keys are verified at admission, a malformed key must not stop
+                    // the node from replaying the chain
+                    let Ok(validator) = deserialize_vk(&validator) else {
+                        println!(
+                            "{}",
+                            format_args!(
+                                "{} Skipped malformed validator key",
+                                "[Warning]".yellow()
+                            )
+                        );
+                        continue;
+                    };
+                    if !self.validators.contains(&validator) {
+                        self.validators.push(validator);
+                    }
+                }
+                ValidatorSetChange::RemoveValidator(validator) => {
+                    let Ok(validator) = deserialize_vk(&validator) else {
+                        println!(
+                            "{}",
+                            format_args!(
+                                "{} Skipped malformed validator key",
+                                "[Warning]".yellow()
+                            )
+                        );
+                        continue;
+                    };
+                    if self.validators.len() > 1 {
+                        self.validators.retain(|v| v != &validator);
+                    }
+                }
+                ValidatorSetChange::SetConsensusThreshold(consensus_threshold) => {
+                    self.consensus_threshold = consensus_threshold;
+                }
+            }
+        }
+        // changes that were approved independently can conflict
+        if let Some(consensus_threshold) = self.consensus_threshold {
+            if consensus_threshold as usize > self.validators.len() {
+                println!(
+                    "{}",
+                    format_args!(
+                        "{} Consensus threshold exceeds the validator set, using the BFT quorum",
+                        "[Warning]".yellow()
+                    )
+                );
+                self.consensus_threshold = None;
+            }
+        }
+        self.validator_sets.insert(
+            next_height,
+            ValidatorSet {
+                validators: self
+                    .validators
+                    .iter()
+                    .map(|validator| validator.to_sec1_bytes().to_vec())
+                    .collect(),
+                consensus_threshold: self.consensus_threshold,
+            },
+        );
+        println!(
+            "{}",
+            format_args!(
+                "{} Validator set changed at height {}: {} validators",
+                "[Info]".green(),
+                next_height,
+                self.validators.len()
+            )
+        );
+    }
+    // the commitment's signature must have been verified,
+    // returns evidence if the validator signed a different header at this height and round before
+    pub fn record_commitment(
+        &mut self,
+        header: &BlockHeader,
+        commitment: &BlockCommitment,
+    ) -> Option<EquivocationEvidence> {
+        let signed_header = SignedHeader {
+            header: header.clone(),
+            signature: commitment.signature.clone(),
+        };
+        let key = (header.height, header.round, commitment.validator.clone());
+        match self.signed_headers.get(&key) {
+            Some(first) if &first.header != header => Some(EquivocationEvidence {
+                validator: commitment.validator.clone(),
+                height: header.height,
+                round: header.round,
+                first: first.clone(),
+                second: signed_header,
+            }),
+            Some(_) => None,
+            None => {
+                self.signed_headers.insert(key, signed_header);
+                None
+            }
+        }
+    }
+    // signed headers are only kept for heights that are not final yet
+    pub fn prune_signed_headers(&mut self, finalized_height: u32) {
+        self.signed_headers
+            .retain(|(height, _, _), _| *height > finalized_height);
+    }
+    // called whenever a new Block is stored
+    pub fn reset_view(&mut self) {
+        self.skipped_rounds = 0;
+        self.last_view_change = 0;
+        self.failed_leaders = Vec::new();
+        self.timeout_votes = HashMap::new();
+        self.timed_out = false;
+    }
+}
+#[test]
+fn test_in_memory_block_store_heights() {
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let mut block_store = InMemoryBlockStore::default();
+    block_store
+        .commit_block(genesis.block(), None, &Root::empty(), TrieNodes::new())
+        .unwrap();
+    assert_eq!(block_store.height().unwrap(), 0);
+    let mut block = genesis.block();
+    block.header.height = 1;
+    block_store
+        .commit_block(block.clone(), None, &Root::empty(), TrieNodes::new())
+        .unwrap();
+    // blocks are stored at their own height
+    assert_eq!(block_store.height().unwrap(), 1);
+    assert_eq!(block_store.latest_block().unwrap().hash(), block.hash());
+    assert!(block_store.get_block_by_height(2).unwrap().is_none());
+}
+#[test]
+fn test_import_snapshot_over_shorter_chain() {
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let mut block_store = InMemoryBlockStore::default();
+    block_store
+        .commit_block(genesis.block(), None, &Root::empty(), TrieNodes::new())
+        .unwrap();
+    let transaction = Transaction {
+        data: vec![1],
+        timestamp: 0,
+        governance: None,
+        envelope: None,
+        priority_fee: 0,
+    };
+    let mut block = genesis.block();
+    block.header.height = 1;
+    block.transactions = vec![transaction.clone()];
+    block_store
+        .commit_block(block, None, &Root::empty(), TrieNodes::new())
+        .unwrap();
+    let mut snapshot_block = genesis.block();
+    snapshot_block.header.height = 3;
+    let finalized = FinalizedTransaction {
+        height: 1,
+        position: 0,
+        transaction,
+    };
+    block_store
+        .import_snapshot(
+            CertifiedBlock {
+                block: snapshot_block,
+                certificate: FinalityCertificate {
+                    height: 3,
+                    block_hash: vec![],
+                    commitments: vec![],
+                },
+            },
+            &[finalized],
+            &Root::empty(),
+            TrieNodes::new(),
+        )
+        .unwrap();
+    // the local Block is reduced to its header, its Transaction is part of the base
+    assert_eq!(block_store.height().unwrap(), 3);
+    assert!(block_store.get_block_by_height(1).unwrap().is_none());
+    assert!(block_store.get_header_by_height(1).unwrap().is_some());
+    assert!(block_store.get_trie_root_by_height(1).unwrap().is_none());
+    assert_eq!(block_store.get_base_height().unwrap(), Some(3));
+    assert_eq!(block_store.get_finalized_transactions(3).unwrap().len(), 1);
+}
+#[test]
+fn test_in_memory_pool_reservations() {
+    let entry = |data: u8| PoolEntry {
+        transaction: Transaction {
+            data: vec![data],
+            timestamp: 0,
+            governance: None,
+            envelope: None,
+            priority_fee: 0,
+        },
+        received_at: 0,
+        origin: None,
+        reserved: false,
+    };
+    let mut pool_state = InMemoryTransactionPool::default();
+    pool_state.insert_transaction(entry(0)).unwrap();
+    pool_state.insert_transaction(entry(1)).unwrap();
+    let proposed = vec![entry(0).transaction.hash()];
+    pool_state.reserve_transactions(&proposed).unwrap();
+    let reserved = |pool_state: &InMemoryTransactionPool| -> Vec<bool> {
+        pool_state
+            .get_entries()
+            .unwrap()
+            .iter()
+            .map(|entry| entry.reserved)
+            .collect()
+    };
+    assert_eq!(reserved(&pool_state), vec![true, false]);
+    // a failed proposal returns its transactions to the pool
+    pool_state.release_reservations().unwrap();
+    assert_eq!(reserved(&pool_state), vec![false, false]);
+    assert!(pool_state.contains_transaction(&proposed[0]).unwrap());
+}
+#[test]
+fn test_no_equivocation_after_view_change() {
+    use k256::ecdsa::{signature::Signer, Signature};
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let signing_key = SigningKey::random(&mut rand_core::OsRng);
+    let mut consensus_state = InMemoryConsensus::from_genesis(&genesis, signing_key.clone());
+    let mut block_store = InMemoryBlockStore::default();
+    let mut block = genesis.block();
+    block.header.height = 1;
+    block.header.round = 1;
+    let signature: Signature = signing_key.sign(&block.to_bytes());
+    let commitment = BlockCommitment {
+        signature: signature.to_bytes().to_vec(),
+        validator: consensus_state.local_validator.to_sec1_bytes().to_vec(),
+        timestamp: 0,
+    };
+    block_store
+        .set_last_signed(LastSigned {
+            height: 1,
+            round: 1,
+            hash: block.hash(),
+        })
+        .unwrap();
+    assert!(consensus_state
+        .record_commitment(&block.header, &commitment)
+        .is_none());
+    let last_signed = block_store.get_last_signed().unwrap().unwrap();
+    // a different header in the same round is never signed, the same header can be signed again
+    let mut conflicting = block.clone();
+    conflicting.header.timestamp += 1;
+    assert!(!last_signed.permits(&conflicting.header));
+    assert!(last_signed.permits(&block.header));
+    // the next leader proposes a different header at the same height in the next round
+    consensus_state.apply_view_change(block.header.timestamp);
+    let mut reproposal = conflicting.clone();
+    reproposal.header.round = 2;
+    assert!(last_signed.permits(&reproposal.header));
+    let signature: Signature = signing_key.sign(&reproposal.to_bytes());
+    let reproposal_commitment = BlockCommitment {
+        signature: signature.to_bytes().to_vec(),
+        ..commitment.clone()
+    };
+    assert!(consensus_state
+        .record_commitment(&reproposal.header, &reproposal_commitment)
+        .is_none());
+    // signing a second header in the first round is evidence
+    let signature: Signature = signing_key.sign(&conflicting.to_bytes());
+    let conflicting_commitment = BlockCommitment {
+        signature: signature.to_bytes().to_vec(),
+        ..commitment
+    };
+    let evidence = consensus_state
+        .record_commitment(&conflicting.header, &conflicting_commitment)
+        .unwrap();
+    assert_eq!((evidence.height, evidence.round), (1, 1));
+    assert!(block_store.insert_evidence(evidence.clone()).unwrap());
+    assert!(!block_store.insert_evidence(evidence).unwrap());
+}
+#[test]
+fn test_timeout_votes_per_view() {
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let signing_key = SigningKey::random(&mut rand_core::OsRng);
+    let mut consensus_state = InMemoryConsensus::from_genesis(&genesis, signing_key);
+    let quorum = consensus_state.quorum();
+    let vote = |validator: u8, round: u32| TimeoutVote {
+        validator: vec![validator],
+        chain_id: genesis.chain_id.clone(),
+        height: 1,
+        round,
+        signature: vec![],
+    };
+    consensus_state.enter_view(1, 1);
+    // votes for another round and repeated votes don't count
+    assert!(consensus_state.add_timeout_vote(vote(0, 2)).is_none());
+    for validator in 0..quorum as u8 - 1 {
+        assert!(consensus_state
+            .add_timeout_vote(vote(validator, 1))
+            .is_none());
+        assert!(consensus_state.add_timeout_vote(vote(0, 1)).is_none());
+    }
+    // the round moved on before the quorum was reached
+    consensus_state.enter_view(1, 2);
+    assert!(consensus_state
+        .add_timeout_vote(vote(quorum as u8 - 1, 1))
+        .is_none());
+    for validator in 0..quorum as u8 - 1 {
+        assert!(consensus_state
+            .add_timeout_vote(vote(validator, 2))
+            .is_none());
     }
+    let certificate = consensus_state
+        .add_timeout_vote(vote(quorum as u8 - 1, 2))
+        .unwrap();
+    assert_eq!((certificate.height, certificate.round), (1, 2));
+    assert_eq!(certificate.votes.len() as u32, quorum);
 }
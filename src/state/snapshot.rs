@@ -0,0 +1,179 @@
+use crate::{
+    config::genesis::Genesis,
+    consensus::logic::verify_governance_transaction,
+    exclusion::{membership_proofs, sequenced_root, SequencedLeaf},
+    state::server::{BlockStore, InMemoryConsensus},
+    types::{
+        CertifiedBlock, FinalizedTransaction, SnapshotChunk, SnapshotEntry, SnapshotManifest,
+        Transaction,
+    },
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+
+// snapshots are produced whenever a multiple of this height is finalized
+pub const SNAPSHOT_INTERVAL: u32 = 100;
+pub const SNAPSHOT_CHUNK_SIZE: u32 = 256;
+
+pub struct Snapshot {
+    pub manifest: SnapshotManifest,
+    pub chunks: Vec<SnapshotChunk>,
+}
+// the latest snapshot height at or below this height
+pub fn snapshot_height(height: u32) -> Option<u32> {
+    let snapshot_height = height - height % SNAPSHOT_INTERVAL;
+    (snapshot_height > 0).then_some(snapshot_height)
+}
+// the Transactions finalized up to the Block are sorted like the leaves
+// of its sequenced root, so that every chunk can be proven on its own
+pub fn build_snapshot(block_state: &dyn BlockStore, height: u32) -> Result<Snapshot> {
+    let block = block_state
+        .get_block_by_height(height)?
+        .ok_or_else(|| anyhow!("Block is missing: {}", height))?;
+    let certificate = block_state
+        .get_certificate_by_height(height)?
+        .ok_or_else(|| anyhow!("Certificate is missing: {}", height))?;
+    let mut finalized = block_state.get_finalized_transactions(height)?;
+    finalized.sort_by_cached_key(|finalized| finalized.transaction.hash());
+    let sorted_leaves: Vec<SequencedLeaf> = finalized
+        .iter()
+        .map(|finalized| SequencedLeaf {
+            hash: finalized.transaction.hash(),
+            height: finalized.height,
+            position: finalized.position,
+        })
+        .collect();
+    if sequenced_root(&sorted_leaves) != block.header.sequenced_root {
+        bail!(
+            "Stored Transactions don't match the sequenced root at {}",
+            height
+        );
+    }
+    let entries: Vec<SnapshotEntry> = finalized
+        .into_iter()
+        .zip(membership_proofs(&sorted_leaves))
+        .map(|(finalized, proof)| SnapshotEntry { finalized, proof })
+        .collect();
+    let chunks: Vec<SnapshotChunk> = entries
+        .chunks(SNAPSHOT_CHUNK_SIZE as usize)
+        .enumerate()
+        .map(|(index, entries)| SnapshotChunk {
+            height,
+            index: index as u32,
+            entries: entries.to_vec(),
+        })
+        .collect();
+    Ok(Snapshot {
+        manifest: SnapshotManifest {
+            certified_block: CertifiedBlock { block, certificate },
+            leaf_count: sorted_leaves.len() as u64,
+            chunk_size: SNAPSHOT_CHUNK_SIZE,
+            chunk_count: chunks.len() as u32,
+        },
+        chunks,
+    })
+}
+// rebuilds the validator set from the governance transactions finalized below `height`,
+// as the Blocks below a snapshot are not stored. with `verify` every governance
+// transaction must be approved by the validators of its height
+pub fn replay_governance(
+    genesis: &Genesis,
+    consensus_state: &mut InMemoryConsensus,
+    transactions: &[FinalizedTransaction],
+    height: u32,
+    verify: bool,
+) -> Result<()> {
+    let mut governance_transactions: BTreeMap<u32, Vec<Transaction>> = BTreeMap::new();
+    for finalized in transactions {
+        if finalized.height < height && finalized.transaction.governance.is_some() {
+            governance_transactions
+                .entry(finalized.height)
+                .or_default()
+                .push(finalized.transaction.clone());
+        }
+    }
+    // changes activate per height, so heights without transactions are applied as well
+    let mut block = genesis.block();
+    for block_height in 1..height {
+        block.header.height = block_height;
+        block.transactions = governance_transactions
+            .remove(&block_height)
+            .unwrap_or_default();
+        if verify {
+            for governance in block
+                .transactions
+                .iter()
+                .filter_map(|transaction| transaction.governance.as_ref())
+            {
+                verify_governance_transaction(
+                    governance,
+                    &genesis.chain_id,
+                    &consensus_state.validators,
+                    consensus_state.quorum(),
+                    block_height,
+                )
+                .with_context(|| format!("Invalid Governance Transaction at {}", block_height))?;
+            }
+        }
+        consensus_state.apply_governance(&block);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_height() {
+    assert_eq!(snapshot_height(0), None);
+    assert_eq!(snapshot_height(SNAPSHOT_INTERVAL - 1), None);
+    assert_eq!(snapshot_height(SNAPSHOT_INTERVAL), Some(SNAPSHOT_INTERVAL));
+    assert_eq!(
+        snapshot_height(SNAPSHOT_INTERVAL * 2 + 1),
+        Some(SNAPSHOT_INTERVAL * 2)
+    );
+}
+#[test]
+fn test_build_snapshot() {
+    use crate::{
+        consensus::logic::{verify_snapshot_chunk, verify_snapshot_manifest},
+        handlers::sequenced_leaves,
+        state::{server::InMemoryBlockStore, trie::TrieNodes},
+        types::FinalityCertificate,
+        Root,
+    };
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let mut block_state = InMemoryBlockStore::default();
+    block_state
+        .commit_block(genesis.block(), None, &Root::empty(), TrieNodes::new())
+        .unwrap();
+    for height in 1..=2u32 {
+        let mut block = genesis.block();
+        block.header.height = height;
+        block.transactions = (0..3u8)
+            .map(|i| Transaction {
+                data: vec![height as u8, i],
+                timestamp: 0,
+                governance: None,
+                envelope: None,
+                priority_fee: 0,
+            })
+            .collect();
+        let mut leaves = block_state.get_sequenced_leaves(height).unwrap();
+        leaves.extend(sequenced_leaves(height, &block.transactions));
+        block.header.sequenced_root = sequenced_root(&leaves);
+        let certificate = FinalityCertificate {
+            height,
+            block_hash: block.hash(),
+            commitments: vec![],
+        };
+        block_state
+            .commit_block(block, Some(certificate), &Root::empty(), TrieNodes::new())
+            .unwrap();
+    }
+    let snapshot = build_snapshot(&block_state, 2).unwrap();
+    assert_eq!(snapshot.manifest.leaf_count, 6);
+    assert!(verify_snapshot_manifest(&snapshot.manifest).is_ok());
+    for chunk in &snapshot.chunks {
+        assert!(verify_snapshot_chunk(chunk, &snapshot.manifest).is_ok());
+    }
+    // the snapshot of a Block that was not finalized can't be built
+    assert!(build_snapshot(&block_state, 3).is_err());
+}
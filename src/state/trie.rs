@@ -0,0 +1,133 @@
+use crate::{state::server::BlockStore, Root};
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use patricia_trie::store::{db::Database, types::Node};
+use std::collections::HashMap;
+
+// trie nodes by the key the trie stores them under
+pub type TrieNodes = HashMap<Vec<u8>, Node>;
+
+// the trie of the block store with the nodes written since its latest Block. the staged nodes
+// only reach the store together with the Block that commits their root, nodes of proposals
+// that never finalize are dropped with the staged trie
+pub struct StagedTrie<'a> {
+    block_state: Option<&'a dyn BlockStore>,
+    staged: TrieNodes,
+}
+impl<'a> StagedTrie<'a> {
+    pub fn new(block_state: &'a dyn BlockStore) -> Self {
+        Self {
+            block_state: Some(block_state),
+            staged: HashMap::new(),
+        }
+    }
+    // a trie that only holds the given nodes, independent of the store
+    pub fn detached(nodes: TrieNodes) -> Self {
+        Self {
+            block_state: None,
+            staged: nodes,
+        }
+    }
+    pub fn into_nodes(self) -> TrieNodes {
+        self.staged
+    }
+}
+impl Database for StagedTrie<'_> {
+    fn get(&mut self, key: &[u8]) -> Option<Node> {
+        if let Some(node) = self.staged.get(key) {
+            return Some(node.clone());
+        }
+        // a missing node fails the root check of the Block that needed it
+        match self.block_state?.get_trie_node(key) {
+            Ok(node) => node,
+            Err(e) => {
+                println!(
+                    "{}",
+                    format_args!("{} Failed to read trie node: {:#}", "[Error]".red(), e)
+                );
+                None
+            }
+        }
+    }
+    fn insert(&mut self, key: &[u8], node: Node) {
+        self.staged.insert(key.to_vec(), node);
+    }
+}
+// copies the stored nodes reachable from the root into `reachable`. nodes are content
+// addressed, so the subtree of a node that was already copied is skipped
+pub fn mark_reachable(
+    block_state: &dyn BlockStore,
+    root: &Root,
+    reachable: &mut TrieNodes,
+) -> Result<()> {
+    let mut pending = child_keys(&Node::Root(root.clone()));
+    while let Some(key) = pending.pop() {
+        if reachable.contains_key(&key) {
+            continue;
+        }
+        let node = block_state
+            .get_trie_node(&key)?
+            .ok_or_else(|| anyhow!("Trie node is missing"))?;
+        pending.extend(child_keys(&node));
+        reachable.insert(key, node);
+    }
+    Ok(())
+}
+fn child_keys(node: &Node) -> Vec<Vec<u8>> {
+    match node {
+        Node::Root(root) => [&root.left, &root.right]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+        Node::Branch(branch) => [&branch.left, &branch.right]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect(),
+        Node::Leaf(_) => Vec::new(),
+    }
+}
+
+#[test]
+fn test_staged_trie() {
+    use crate::{
+        handlers::apply_transactions,
+        state::server::InMemoryBlockStore,
+        types::{Block, Transaction},
+        Root,
+    };
+    let transaction = |data: u8| Transaction {
+        data: vec![data],
+        timestamp: 0,
+        governance: None,
+        envelope: None,
+        priority_fee: 0,
+    };
+    let genesis = crate::config::genesis::Genesis::load("resources/genesis.json").unwrap();
+    let mut block_state = InMemoryBlockStore::default();
+    let mut trie = StagedTrie::new(&block_state);
+    let trie_root = apply_transactions(&mut trie, Root::empty(), &[transaction(0)]);
+    let trie_nodes = trie.into_nodes();
+    // nothing is stored before the Block that commits the root
+    assert!(block_state.trie_nodes.is_empty());
+    let mut block: Block = genesis.block();
+    block.header.height = 1;
+    block.header.state_root = trie_root.hash.clone();
+    block_state
+        .commit_block(block, None, &trie_root, trie_nodes)
+        .unwrap();
+    let node_count = block_state.trie_nodes.len();
+    // a candidate applied on top of the stored trie leaves the store untouched
+    let mut candidate = StagedTrie::new(&block_state);
+    let candidate_root = apply_transactions(&mut candidate, trie_root.clone(), &[transaction(1)]);
+    assert_ne!(candidate_root.hash, trie_root.hash);
+    assert!(!candidate.into_nodes().is_empty());
+    assert_eq!(block_state.trie_nodes.len(), node_count);
+    assert!(patricia_trie::merkle::merkle_proof(
+        &mut StagedTrie::new(&block_state),
+        transaction(0).key(),
+        Node::Root(trie_root),
+    )
+    .is_some());
+}
@@ -0,0 +1,490 @@
+use crate::config::genesis::Genesis;
+use crate::consensus::logic::{verify_snapshot_chunk, verify_snapshot_manifest};
+use crate::gossipper::{Peer, GENESIS_HASH_HEADER, INCOMPATIBLE_PEER_BACKOFF};
+use crate::handlers::{apply_certified_block, apply_snapshot};
+use crate::types::{Block, CertifiedBlock, NodeStatus, SnapshotChunk, SnapshotManifest};
+use crate::ServerState;
+use anyhow::Result;
+use colored::Colorize;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::RwLock;
+
+// peers serve at most this many Blocks per request
+pub const SYNC_RANGE_SIZE: u32 = 32;
+// ranges that are downloaded at once before they are applied
+const MAX_PARALLEL_RANGES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncRange {
+    pub from: u32,
+    pub to: u32,
+    pub peer: Peer,
+}
+// splits the heights after the local tip into ranges, every range is
+// requested from one of the peers that has all of its Blocks.
+// peers can advertise any height, so only the next few ranges are planned per pass
+pub fn plan_ranges(local_height: u32, peer_heights: &[(Peer, u32)]) -> Vec<SyncRange> {
+    let target_height = peer_heights
+        .iter()
+        .map(|(_, height)| *height)
+        .max()
+        .unwrap_or(0);
+    let mut ranges: Vec<SyncRange> = Vec::new();
+    let mut next = local_height.checked_add(1);
+    while let Some(from) = next {
+        if from > target_height || ranges.len() == MAX_PARALLEL_RANGES {
+            break;
+        }
+        let to = from.saturating_add(SYNC_RANGE_SIZE - 1).min(target_height);
+        // the peer at the target height always qualifies
+        let candidates: Vec<&Peer> = peer_heights
+            .iter()
+            .filter(|(_, height)| *height >= to)
+            .map(|(peer, _)| peer)
+            .collect();
+        ranges.push(SyncRange {
+            from,
+            to,
+            peer: candidates[ranges.len() % candidates.len()].clone(),
+        });
+        next = to.checked_add(1);
+    }
+    ranges
+}
+fn cap_peer_height(peer_heights: &mut [(Peer, u32)], peer: &Peer, height: u32) {
+    for (_, peer_height) in peer_heights.iter_mut().filter(|(other, _)| other == peer) {
+        *peer_height = (*peer_height).min(height);
+    }
+}
+async fn request<T: DeserializeOwned>(
+    client: Client,
+    peer: &Peer,
+    path: String,
+    genesis_hash: String,
+    timeout: u64,
+) -> Option<T> {
+    let response = client
+        .get(format!("http://{}{}", &peer.address, path))
+        .header(GENESIS_HASH_HEADER, genesis_hash)
+        .timeout(Duration::from_secs(timeout))
+        .send()
+        .await
+        .ok()?;
+    serde_json::from_str(&response.text().await.ok()?).ok()
+}
+// peers of a different network are backed off, a peer at our height with
+// a different tip is followed by neither side and only reported
+fn handshake(genesis: &Genesis, local_tip: &Block, peer: &Peer, status: &NodeStatus) -> bool {
+    if status.chain_id != genesis.chain_id || status.genesis_hash != genesis.hash_hex() {
+        println!(
+            "{}",
+            format_args!(
+                "{} Peer {} runs chain {} with a different genesis, retrying in {}s",
+                "[Warning]".yellow(),
+                &peer.address,
+                status.chain_id,
+                INCOMPATIBLE_PEER_BACKOFF
+            )
+        );
+        return false;
+    }
+    if status.height == local_tip.header.height && status.tip_hash != local_tip.hash() {
+        println!(
+            "{}",
+            format_args!(
+                "{} Peer {} finalized a different Block #{}",
+                "[Warning]".yellow(),
+                &peer.address,
+                status.height
+            )
+        );
+    }
+    true
+}
+// downloads missing Blocks from all peers in parallel and applies those that verify,
+// consensus only starts once no peer is ahead of us. returns whether we caught up
+pub async fn synchronize(state: Arc<RwLock<ServerState>>) -> Result<bool> {
+    let (client, peers, genesis_hash, local_tip) = {
+        let state_lock = state.read().await;
+        (
+            state_lock.local_gossipper.client.clone(),
+            state_lock.local_gossipper.remote_peers(),
+            state_lock.genesis.hash_hex(),
+            state_lock.block_state.latest_block()?,
+        )
+    };
+    let local_height = local_tip.header.height;
+    let status_requests: Vec<_> = peers
+        .into_iter()
+        .map(|peer| {
+            let client = client.clone();
+            let genesis_hash = genesis_hash.clone();
+            tokio::spawn(async move {
+                request(client, &peer, "/get/status".to_string(), genesis_hash, 3)
+                    .await
+                    .map(|status: NodeStatus| (peer, status))
+            })
+        })
+        .collect();
+    // unreachable peers are kept and asked again in the next pass
+    let mut peer_heights: Vec<(Peer, u32)> = Vec::new();
+    let mut peer_statuses: Vec<NodeStatus> = Vec::new();
+    for status_request in status_requests {
+        if let Ok(Some((peer, status))) = status_request.await {
+            let mut state_lock = state.write().await;
+            if handshake(&state_lock.genesis, &local_tip, &peer, &status) {
+                state_lock.local_gossipper.mark_compatible(&peer.address);
+                peer_heights.push((peer, status.height));
+                peer_statuses.push(status);
+            } else {
+                state_lock.local_gossipper.mark_incompatible(&peer.address);
+            }
+        }
+    }
+    let target_height = peer_heights
+        .iter()
+        .map(|(_, height)| *height)
+        .max()
+        .unwrap_or(0);
+    // a node without Blocks, or behind the Blocks its peers still serve, starts from the
+    // latest snapshot and synchronizes the rest. Blocks are synchronized from our tip
+    // if no valid snapshot was served
+    if let Some(snapshot_height) = bootstrap_height(local_height, &peer_statuses) {
+        let snapshot_peers: Vec<Peer> = peer_heights
+            .iter()
+            .zip(&peer_statuses)
+            .filter(|(_, status)| status.snapshot_height == Some(snapshot_height))
+            .map(|((peer, _), _)| peer.clone())
+            .collect();
+        if bootstrap(
+            Arc::clone(&state),
+            client.clone(),
+            snapshot_peers,
+            genesis_hash.clone(),
+        )
+        .await?
+        {
+            return Ok(false);
+        }
+    }
+    let ranges = plan_ranges(local_height, &peer_heights);
+    if !ranges.is_empty() {
+        println!(
+            "{}",
+            format_args!(
+                "{} Synchronizing Blocks {} to {} from {} peers",
+                "[Info]".green(),
+                local_height + 1,
+                target_height,
+                peer_heights.len()
+            )
+        );
+    }
+    let range_requests: Vec<_> = ranges
+        .into_iter()
+        .map(|range| {
+            let client = client.clone();
+            let genesis_hash = genesis_hash.clone();
+            tokio::spawn(async move {
+                let path = format!("/get/blocks/{}/{}", range.from, range.to);
+                let certified_blocks: Option<Vec<CertifiedBlock>> =
+                    request(client, &range.peer, path, genesis_hash, 30).await;
+                (range, certified_blocks)
+            })
+        })
+        .collect();
+    // the lock is only taken once every download finished
+    let mut downloads: Vec<(SyncRange, Option<Vec<CertifiedBlock>>)> = Vec::new();
+    for range_request in range_requests {
+        downloads.push(range_request.await?);
+    }
+    let mut state_lock = state.write().await;
+    // ranges are applied in order, a missing or invalid range leaves a gap
+    // that later ranges can't be linked across, they are requested again.
+    // a peer is not trusted above the Blocks it failed to serve, so that
+    // an advertised height that can't be served doesn't keep us from catching up
+    'ranges: for (range, certified_blocks) in downloads {
+        let certified_blocks = match certified_blocks {
+            Some(certified_blocks) if !certified_blocks.is_empty() => certified_blocks,
+            _ => {
+                println!(
+                    "{}",
+                    format_args!(
+                        "{} Peer {} did not serve Blocks {} to {}",
+                        "[Warning]".yellow(),
+                        &range.peer.address,
+                        range.from,
+                        range.to
+                    )
+                );
+                cap_peer_height(&mut peer_heights, &range.peer, range.from - 1);
+                break;
+            }
+        };
+        for certified_block in certified_blocks {
+            let height = certified_block.block.header.height;
+            // Blocks may have been finalized through consensus in the meantime
+            if height <= state_lock.block_state.height()? {
+                continue;
+            }
+            if !apply_certified_block(&mut state_lock, certified_block)? {
+                println!(
+                    "{}",
+                    format_args!(
+                        "{} Peer {} served an invalid Block",
+                        "[Warning]".yellow(),
+                        &range.peer.address
+                    )
+                );
+                cap_peer_height(&mut peer_heights, &range.peer, height.saturating_sub(1));
+                break 'ranges;
+            }
+        }
+        let local_height = state_lock.block_state.height()?;
+        if local_height < range.to {
+            println!(
+                "{}",
+                format_args!(
+                    "{} Peer {} only served Blocks up to {}",
+                    "[Warning]".yellow(),
+                    &range.peer.address,
+                    local_height
+                )
+            );
+            cap_peer_height(&mut peer_heights, &range.peer, local_height);
+            break;
+        }
+    }
+    let target_height = peer_heights
+        .iter()
+        .map(|(_, height)| *height)
+        .max()
+        .unwrap_or(0);
+    let caught_up = state_lock.block_state.height()? >= target_height;
+    if caught_up && !state_lock.synchronized {
+        println!(
+            "{}",
+            format_args!(
+                "{} Caught up with peers, starting consensus",
+                "[Info]".green()
+            )
+        );
+    }
+    state_lock.synchronized = caught_up;
+    Ok(caught_up)
+}
+// downloads the chunks of a snapshot from all peers that serve it and
+// applies it once every chunk was verified against the manifest
+async fn bootstrap(
+    state: Arc<RwLock<ServerState>>,
+    client: Client,
+    peers: Vec<Peer>,
+    genesis_hash: String,
+) -> Result<bool> {
+    let manifest: SnapshotManifest = match request(
+        client.clone(),
+        &peers[0],
+        "/get/snapshot".to_string(),
+        genesis_hash.clone(),
+        3,
+    )
+    .await
+    {
+        Some(manifest) => manifest,
+        None => return Ok(false),
+    };
+    if let Err(e) = verify_snapshot_manifest(&manifest) {
+        println!(
+            "{}",
+            format_args!(
+                "{} Peer {} served an invalid snapshot: {}",
+                "[Warning]".yellow(),
+                &peers[0].address,
+                e
+            )
+        );
+        return Ok(false);
+    }
+    println!(
+        "{}",
+        format_args!(
+            "{} Bootstrapping from snapshot at height {}: {} chunks from {} peers",
+            "[Info]".green(),
+            manifest.certified_block.block.header.height,
+            manifest.chunk_count,
+            peers.len()
+        )
+    );
+    let indices: Vec<u32> = (0..manifest.chunk_count).collect();
+    let mut chunks: Vec<SnapshotChunk> = Vec::new();
+    for batch in indices.chunks(MAX_PARALLEL_RANGES) {
+        let chunk_requests: Vec<_> = batch
+            .iter()
+            .map(|index| {
+                let client = client.clone();
+                let genesis_hash = genesis_hash.clone();
+                let peer = peers[*index as usize % peers.len()].clone();
+                let index = *index;
+                let path = format!("/get/snapshot/{}", index);
+                tokio::spawn(async move {
+                    let chunk: Option<SnapshotChunk> =
+                        request(client, &peer, path, genesis_hash, 30).await;
+                    (peer, index, chunk)
+                })
+            })
+            .collect();
+        for chunk_request in chunk_requests {
+            let (peer, index, chunk) = chunk_request.await?;
+            // a chunk of another index would pass verification on its own
+            let verified = chunk
+                .filter(|chunk| chunk.index == index)
+                .map(|chunk| verify_snapshot_chunk(&chunk, &manifest).map(|_| chunk));
+            match verified {
+                Some(Ok(chunk)) => chunks.push(chunk),
+                _ => {
+                    println!(
+                        "{}",
+                        format_args!(
+                            "{} Peer {} did not serve a valid snapshot chunk",
+                            "[Warning]".yellow(),
+                            &peer.address
+                        )
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+    }
+    let mut state_lock = state.write().await;
+    // Blocks may have been applied in the meantime
+    if state_lock.block_state.height()? >= manifest.certified_block.block.header.height {
+        return Ok(false);
+    }
+    apply_snapshot(&mut state_lock, manifest, chunks)
+}
+// the latest snapshot that is ahead of us, if we have no Blocks
+// or no peer serves the Block after our tip anymore
+fn bootstrap_height(local_height: u32, peer_statuses: &[NodeStatus]) -> Option<u32> {
+    let snapshot_height = peer_statuses
+        .iter()
+        .filter_map(|status| status.snapshot_height)
+        .max()
+        .filter(|snapshot_height| *snapshot_height > local_height)?;
+    let next_block_served = peer_statuses.iter().any(|status| {
+        status.height > local_height && status.pruned_height.unwrap_or(0) <= local_height
+    });
+    (local_height == 0 || !next_block_served).then_some(snapshot_height)
+}
+
+#[cfg(test)]
+fn test_peer(address: &str) -> Peer {
+    use crate::crypto::ecdsa::Keypair;
+    Peer {
+        address: address.to_string(),
+        validator: Keypair::new().vk,
+    }
+}
+#[test]
+fn test_plan_ranges() {
+    let (a, b) = (test_peer("a"), test_peer("b"));
+    assert!(plan_ranges(10, &[(a.clone(), 10)]).is_empty());
+    let ranges = plan_ranges(0, &[(a.clone(), 100), (b.clone(), 40)]);
+    let bounds: Vec<(u32, u32)> = ranges.iter().map(|range| (range.from, range.to)).collect();
+    assert_eq!(bounds, vec![(1, 32), (33, 64), (65, 96), (97, 100)]);
+    // ranges are spread over the peers that have all of their Blocks
+    assert_eq!(ranges[0].peer, b);
+    assert_eq!(ranges[1].peer, a);
+    assert_eq!(ranges[2].peer, a);
+    // a peer at the highest possible height only gets the next window of ranges
+    let ranges = plan_ranges(0, &[(a.clone(), u32::MAX)]);
+    assert_eq!(ranges.len(), MAX_PARALLEL_RANGES);
+    assert_eq!(
+        ranges[MAX_PARALLEL_RANGES - 1].to,
+        SYNC_RANGE_SIZE * MAX_PARALLEL_RANGES as u32
+    );
+    let ranges = plan_ranges(u32::MAX - 1, &[(a.clone(), u32::MAX)]);
+    let bounds: Vec<(u32, u32)> = ranges.iter().map(|range| (range.from, range.to)).collect();
+    assert_eq!(bounds, vec![(u32::MAX, u32::MAX)]);
+    assert!(plan_ranges(u32::MAX, &[(a, u32::MAX)]).is_empty());
+}
+#[test]
+fn test_cap_peer_height() {
+    let (a, b) = (test_peer("a"), test_peer("b"));
+    let mut peer_heights = vec![(a.clone(), u32::MAX), (b.clone(), 40)];
+    // the peer failed to serve the Blocks after 40, only the other peer is ahead of 10
+    cap_peer_height(&mut peer_heights, &a, 40);
+    assert_eq!(peer_heights, vec![(a.clone(), 40), (b.clone(), 40)]);
+    cap_peer_height(&mut peer_heights, &b, 10);
+    assert!(plan_ranges(10, &peer_heights)
+        .iter()
+        .all(|range| range.peer == a));
+}
+#[test]
+fn test_handshake() {
+    let genesis = Genesis::load("resources/genesis.json").unwrap();
+    let local_tip = genesis.block();
+    let peer = test_peer("a");
+    let status = NodeStatus {
+        chain_id: genesis.chain_id.clone(),
+        genesis_hash: genesis.hash_hex(),
+        height: 5,
+        tip_hash: vec![0; 32],
+        snapshot_height: None,
+        pruned_height: None,
+    };
+    assert!(handshake(&genesis, &local_tip, &peer, &status));
+    // a diverging tip is only reported
+    let diverging = NodeStatus {
+        height: 0,
+        ..status.clone()
+    };
+    assert!(handshake(&genesis, &local_tip, &peer, &diverging));
+    let other_chain = NodeStatus {
+        chain_id: "other".to_string(),
+        ..status.clone()
+    };
+    assert!(!handshake(&genesis, &local_tip, &peer, &other_chain));
+    let other_genesis = NodeStatus {
+        genesis_hash: "00".to_string(),
+        ..status
+    };
+    assert!(!handshake(&genesis, &local_tip, &peer, &other_genesis));
+}
+#[test]
+fn test_bootstrap_height() {
+    let status =
+        |height: u32, snapshot_height: Option<u32>, pruned_height: Option<u32>| NodeStatus {
+            chain_id: String::new(),
+            genesis_hash: String::new(),
+            height,
+            tip_hash: vec![],
+            snapshot_height,
+            pruned_height,
+        };
+    // a node without Blocks starts from the latest snapshot
+    let archive = status(250, Some(200), None);
+    let pruned = status(1250, Some(1200), Some(200));
+    assert_eq!(
+        bootstrap_height(0, &[archive.clone(), pruned.clone()]),
+        Some(1200)
+    );
+    // a node behind the pruned height of every peer that is ahead can't catch up block by block
+    assert_eq!(bootstrap_height(150, &[pruned.clone()]), Some(1200));
+    assert_eq!(
+        bootstrap_height(150, &[archive.clone(), pruned.clone()]),
+        None
+    );
+    assert_eq!(
+        bootstrap_height(300, &[archive, pruned.clone()]),
+        Some(1200)
+    );
+    // the Blocks after the pruned height are still served
+    assert_eq!(bootstrap_height(200, &[pruned.clone()]), None);
+    assert_eq!(bootstrap_height(1200, &[pruned]), None);
+    assert_eq!(
+        bootstrap_height(150, &[status(1250, None, Some(200))]),
+        None
+    );
+}
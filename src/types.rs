@@ -1,45 +1,321 @@
+use crate::exclusion::{ExclusionProof, MembershipProof};
+use k256::sha2::{Digest, Sha256};
+use patricia_trie::merkle::MerkleProof;
 use risc0_zkvm::Receipt;
 use serde::{Deserialize, Serialize};
 pub type GenericSignature = Vec<u8>;
 pub type Timestamp = u32;
 pub type GenericTransactionData = Vec<u8>;
 pub type GenericPublicKey = Vec<u8>;
+pub type GenericHash = Vec<u8>;
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub height: u32,
+    // the round the Block was proposed in, 0 for the genesis Block. re-proposals
+    // after a view change are in a later round and don't count as equivocation
+    pub round: u32,
+    pub chain_id: String,
+    // hash of the previous Block's header,
+    // the genesis Block links to the genesis hash
+    pub parent_hash: GenericHash,
+    pub transactions_root: GenericHash,
+    // root of the trie after applying the transactions
+    pub state_root: Option<GenericHash>,
+    // root over the sorted hashes of all Transactions sequenced up to this Block,
+    // proves that a Transaction was not sequenced
+    pub sequenced_root: GenericHash,
+    pub proposer: GenericPublicKey,
+    pub timestamp: Timestamp,
+}
+impl BlockHeader {
+    pub fn hash(&self) -> GenericHash {
+        Sha256::digest(bincode::serialize(self).unwrap()).to_vec()
+    }
+}
+// a proof against the state root of a Block, the header links
+// the root to a Block that can be checked against its certificate
+#[derive(Serialize, Deserialize)]
+pub struct HeightProof {
+    pub header: BlockHeader,
+    pub proof: MerkleProof,
+}
+// status of a Transaction as seen by this node, a finalized Transaction comes with
+// a proof against the state root of its Block, or of the latest Block once its state was pruned
+#[derive(Serialize, Deserialize)]
+pub enum TransactionStatus {
+    Pending,
+    Included {
+        height: u32,
+        position: u32,
+    },
+    Finalized {
+        height: u32,
+        position: u32,
+        proof: HeightProof,
+    },
+    // evicted from the pool without being finalized
+    Dropped,
+}
+// proves that a Transaction was not sequenced up to the Block
+#[derive(Serialize, Deserialize)]
+pub struct HeightExclusionProof {
+    pub header: BlockHeader,
+    pub proof: ExclusionProof,
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
-    pub height: u32,
+    pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
     pub signature: Option<GenericSignature>,
     pub commitments: Option<Vec<BlockCommitment>>,
-    pub timestamp: Timestamp,
 }
 impl Block {
+    pub fn hash(&self) -> GenericHash {
+        self.header.hash()
+    }
+    // the header is what proposers and validators sign,
+    // it commits to the transactions through the transactions root
     pub fn to_bytes(&self) -> Vec<u8> {
-        let temp_block: Block = Block {
-            height: self.height,
-            transactions: self.transactions.clone(),
-            signature: None,
-            commitments: None,
-            timestamp: self.timestamp,
-        };
-        bincode::serialize(&temp_block).unwrap()
+        bincode::serialize(&self.header).unwrap()
     }
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
     pub data: GenericTransactionData,
     pub timestamp: Timestamp,
+    // system transactions that change the validator set
+    #[serde(default)]
+    pub governance: Option<GovernanceTransaction>,
+    // raw transactions without an envelope have no sender
+    #[serde(default)]
+    pub envelope: Option<TransactionEnvelope>,
+    // transactions with a higher fee are picked first when Blocks are full
+    #[serde(default)]
+    pub priority_fee: u64,
+}
+// the sender signs the transaction including the envelope without the signature,
+// nonces of a sender are used in order and only once
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransactionEnvelope {
+    pub sender: GenericPublicKey,
+    pub nonce: u64,
+    pub chain_id: String,
+    pub signature: GenericSignature,
+}
+impl Transaction {
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize
+    }
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut unsigned = self.clone();
+        if let Some(envelope) = unsigned.envelope.as_mut() {
+            envelope.signature = Vec::new();
+        }
+        bincode::serialize(&unsigned).unwrap()
+    }
+    pub fn hash(&self) -> GenericHash {
+        Sha256::digest(bincode::serialize(self).unwrap()).to_vec()
+    }
+    // the trie key is the bits of the transaction hash,
+    // transactions with the same data don't collide
+    pub fn key(&self) -> Vec<u8> {
+        transaction_key(&self.hash())
+    }
+}
+// the trie key of a Transaction by its hash
+pub fn transaction_key(transaction_hash: &[u8]) -> Vec<u8> {
+    transaction_hash
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+        .collect()
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ValidatorSetChange {
+    AddValidator(GenericPublicKey),
+    RemoveValidator(GenericPublicKey),
+    // None falls back to the BFT quorum
+    SetConsensusThreshold(Option<u32>),
+}
+// a change to the validator set that takes effect at a future height
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GovernanceProposal {
+    pub chain_id: String,
+    pub change: ValidatorSetChange,
+    pub activation_height: u32,
+}
+impl GovernanceProposal {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GovernanceApproval {
+    pub validator: GenericPublicKey,
+    // a signature over the serialized proposal
+    pub signature: GenericSignature,
+}
+// a proposal approved by a quorum of the current validators
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GovernanceTransaction {
+    pub proposal: GovernanceProposal,
+    pub approvals: Vec<GovernanceApproval>,
+}
+// the validator set that is in effect at a height
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidatorSet {
+    pub validators: Vec<GenericPublicKey>,
+    pub consensus_threshold: Option<u32>,
+}
+// sha256 over the ordered transaction hashes
+pub fn transactions_root(transactions: &[Transaction]) -> GenericHash {
+    let mut hasher = Sha256::new();
+    for transaction in transactions {
+        hasher.update(transaction.hash());
+    }
+    hasher.finalize().to_vec()
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlockCommitment {
     // a signature over the serialized
-    // transactions in the Block
+    // header of the Block
     pub signature: GenericSignature,
     pub validator: GenericPublicKey,
     pub timestamp: Timestamp,
 }
+// proof that a quorum of distinct validators
+// signed the header of a finalized Block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FinalityCertificate {
+    pub height: u32,
+    pub block_hash: GenericHash,
+    pub commitments: Vec<BlockCommitment>,
+}
+// a finalized Block as it is served to synchronizing peers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CertifiedBlock {
+    pub block: Block,
+    pub certificate: FinalityCertificate,
+}
+// what a node reports about its chain when peers connect, served at /get/status
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeStatus {
+    pub chain_id: String,
+    // hex encoded
+    pub genesis_hash: String,
+    pub height: u32,
+    pub tip_hash: GenericHash,
+    // height of the latest trie snapshot the node serves
+    #[serde(default)]
+    pub snapshot_height: Option<u32>,
+    // Blocks up to this height were pruned or replaced by a snapshot and are not served
+    #[serde(default)]
+    pub pruned_height: Option<u32>,
+}
+// a Transaction at the position it was first finalized at
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FinalizedTransaction {
+    pub height: u32,
+    pub position: u32,
+    pub transaction: Transaction,
+}
+// the state at a finalized height, split into chunks of the sequenced transactions.
+// the certified Block anchors the chunks through its sequenced root
+// and the rebuilt trie through its state root
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotManifest {
+    pub certified_block: CertifiedBlock,
+    pub leaf_count: u64,
+    pub chunk_size: u32,
+    pub chunk_count: u32,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotChunk {
+    pub height: u32,
+    pub index: u32,
+    pub entries: Vec<SnapshotEntry>,
+}
+// a finalized Transaction with a proof that it is a leaf of the sequenced root
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotEntry {
+    pub finalized: FinalizedTransaction,
+    pub proof: MembershipProof,
+}
+// a header together with a validator's signature over it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedHeader {
+    pub header: BlockHeader,
+    pub signature: GenericSignature,
+}
+// two conflicting headers at the same height and round signed by the same validator,
+// can be verified by anyone who knows the validator set
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EquivocationEvidence {
+    pub validator: GenericPublicKey,
+    pub height: u32,
+    pub round: u32,
+    pub first: SignedHeader,
+    pub second: SignedHeader,
+}
+// the latest header the local validator signed, it never signs a different
+// header at the same or an earlier height and round, not even after a restart
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LastSigned {
+    pub height: u32,
+    pub round: u32,
+    pub hash: GenericHash,
+}
+impl LastSigned {
+    pub fn permits(&self, header: &BlockHeader) -> bool {
+        match (header.height, header.round).cmp(&(self.height, self.round)) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Equal => header.hash() == self.hash,
+            std::cmp::Ordering::Less => false,
+        }
+    }
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ConsensusCommitment {
     pub validator: GenericPublicKey,
+    pub height: u32,
+    pub round: u32,
     pub receipt: Receipt,
+    // a signature over height, round and
+    // the journal of the receipt
+    pub signature: GenericSignature,
+}
+impl ConsensusCommitment {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(self.height, self.round, &self.receipt.journal.bytes)).unwrap()
+    }
+}
+// a validator's vote to leave a round
+// in which no Block was finalized
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeoutVote {
+    pub validator: GenericPublicKey,
+    pub chain_id: String,
+    pub height: u32,
+    pub round: u32,
+    // a signature over chain id, height and round
+    pub signature: GenericSignature,
+}
+impl TimeoutVote {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(&self.chain_id, self.height, self.round)).unwrap()
+    }
+}
+// a quorum of timeout votes for the same chain, height and round
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimeoutCertificate {
+    pub chain_id: String,
+    pub height: u32,
+    pub round: u32,
+    pub votes: Vec<TimeoutVote>,
+}
+impl TimeoutCertificate {
+    // what every vote of the certificate signs
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(&self.chain_id, self.height, self.round)).unwrap()
+    }
 }
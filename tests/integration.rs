@@ -3,14 +3,19 @@ mod tests {
     use l2_sequencer::types::Transaction;
     use patricia_trie::{
         merkle::{verify_merkle_proof, MerkleProof},
-        store::types::{Hashable, Leaf, Root},
+        store::types::Root,
     };
     use prover::generate_random_number;
     use reqwest::{Client, Response};
-    use std::{env, time::Duration};
+    use std::time::Duration;
     use tokio::time::sleep;
     use {
-        l2_sequencer::config::network::PEERS, l2_sequencer::gossipper::Gossipper,
+        k256::ecdsa::{signature::Signer, Signature, VerifyingKey},
+        l2_sequencer::config::{
+            genesis::{load_signing_key, Genesis},
+            network::load_peers,
+        },
+        l2_sequencer::gossipper::Gossipper,
         l2_sequencer::types::ConsensusCommitment,
     };
 
@@ -48,12 +53,15 @@ mod tests {
         let transaction: Transaction = Transaction {
             data: vec![1, 2, 3, 4, 5],
             timestamp: 0,
+            governance: None,
+            envelope: None,
+            priority_fee: 0,
         };
         let transaction_json: String = serde_json::to_string(&transaction).unwrap();
         let transaction_response = submit_transaction(client.clone(), transaction_json).await;
         assert_eq!(
             transaction_response.text().await.unwrap(),
-            "[Ok] Transaction is being sequenced: Transaction { data: [1, 2, 3, 4, 5], timestamp: 0 }"
+            "[Ok] Transaction is being sequenced: Transaction { data: [1, 2, 3, 4, 5], timestamp: 0, governance: None, envelope: None, priority_fee: 0 }"
         );
         let mut node_trie_root: Option<Root> = None;
         // wait a maximum of ~ 5 blocks
@@ -73,17 +81,7 @@ mod tests {
             };
             sleep(Duration::from_secs(5)).await;
         }
-        let mut leaf = Leaf::new(Vec::new(), Some(transaction.data.clone()));
-        leaf.hash();
-        leaf.key = leaf
-            .hash
-            .clone()
-            .unwrap()
-            .iter()
-            .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
-            .collect();
-        leaf.hash();
-        let transaction_key_json = serde_json::to_string(&leaf.key).unwrap();
+        let transaction_key_json = serde_json::to_string(&transaction.key()).unwrap();
         let merkle_proof_response =
             request_merkle_proof(client.clone(), transaction_key_json).await;
         let merkle_proof_json = merkle_proof_response.text().await.unwrap();
@@ -102,30 +100,42 @@ mod tests {
         let client = Client::new();
         let transaction: Transaction = Transaction {
             data: vec![1, 2, 3, 4, 5],
-            timestamp: 0,
+            timestamp: 1,
+            governance: None,
+            envelope: None,
+            priority_fee: 0,
         };
         let transaction_json: String = serde_json::to_string(&transaction).unwrap();
-        // note that currently a transaction may only be submitted to one node
-        // mishandling this can cause the network to crash
+        // scheduling the same transaction twice is rejected, so it
+        // must differ from the one in the merkle proof test
         let transaction_response = submit_transaction(client, transaction_json).await;
         assert_eq!(
             transaction_response.text().await.unwrap(),
-            "[Ok] Transaction is being sequenced: Transaction { data: [1, 2, 3, 4, 5], timestamp: 0 }"
+            "[Ok] Transaction is being sequenced: Transaction { data: [1, 2, 3, 4, 5], timestamp: 1, governance: None, envelope: None, priority_fee: 0 }"
         );
     }
 
     #[tokio::test]
     async fn test_commit() {
         let receipt = generate_random_number(vec![0; 32], vec![0; 32]);
-        let consensus_commitment: ConsensusCommitment = ConsensusCommitment {
-            validator: vec![0; 32],
+        // gossip as validator #2, the peer that runs under this key is skipped
+        let signing_key = load_signing_key("resources/keys/v2.json").unwrap();
+        let local_validator = VerifyingKey::from(&signing_key);
+        let mut consensus_commitment: ConsensusCommitment = ConsensusCommitment {
+            validator: local_validator.to_sec1_bytes().to_vec(),
+            height: 1,
+            round: 1,
             receipt,
+            signature: vec![],
         };
-        let gossipper = Gossipper {
-            peers: PEERS.to_vec(),
-            client: Client::new(),
-        };
-        env::set_var("API_HOST_WITH_PORT", "127.0.0.1:8081");
+        let signature: Signature = signing_key.sign(&consensus_commitment.to_bytes());
+        consensus_commitment.signature = signature.to_bytes().to_vec();
+        let genesis = Genesis::load("resources/genesis.json").unwrap();
+        let gossipper = Gossipper::new(
+            load_peers("resources/peers.json").unwrap(),
+            local_validator,
+            genesis.hash_hex(),
+        );
         gossipper
             .gossip_consensus_commitment(consensus_commitment)
             .await;